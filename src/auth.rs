@@ -0,0 +1,82 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use std::sync::OnceLock;
+
+use crate::tls;
+
+pub const AUTHORIZATION_HEADER: &str = "Authorization";
+
+static TOKEN: OnceLock<String> = OnceLock::new();
+
+// Token shared by all nodes of a network. Set once at startup; without it every route is open.
+pub fn set_token(token: String) -> Result<(), String> {
+    if token.is_empty() {
+        return Err(String::from("Network token is empty"));
+    }
+    TOKEN
+        .set(token)
+        .map_err(|_token| String::from("Network token has already been set"))
+}
+
+pub fn token() -> Option<&'static str> {
+    return TOKEN.get().map(|token| token.as_str());
+}
+
+// Value of the Authorization header sent along with every request to another node
+pub fn bearer() -> Option<String> {
+    return token().map(|token| format!("Bearer {}", token));
+}
+
+// Proof that the request came from a node of the network, or from an admin holding its token and, with
+// TLS, a certificate signed by its CA. Taken by admin and ring-internal routes, while client data routes
+// stay open.
+pub struct Authorized(());
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authorized {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        if !tls::has_client_certificate(request).await {
+            let err = String::from("Missing client certificate");
+            println!("Rejected {} {}: {}", request.method(), request.uri(), err);
+            return Outcome::Error((Status::Unauthorized, err));
+        }
+
+        match check(request.headers().get_one(AUTHORIZATION_HEADER)) {
+            Ok(authorized) => Outcome::Success(authorized),
+            Err(err) => {
+                println!("Rejected {} {}: {}", request.method(), request.uri(), err);
+                Outcome::Error((Status::Unauthorized, err))
+            }
+        }
+    }
+}
+
+// Checks the value of an Authorization header against the token of the network
+pub fn check(authorization: Option<&str>) -> Result<Authorized, String> {
+    let Some(token) = token() else {
+        return Ok(Authorized(()));
+    };
+
+    match authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        None => return Err(String::from("Missing network token")),
+        Some(presented) if constant_time_eq(presented.as_bytes(), token.as_bytes()) => {
+            return Ok(Authorized(()))
+        }
+        Some(_presented) => return Err(String::from("Wrong network token")),
+    }
+}
+
+// Takes as long for a token that is wrong in its first byte as in its last, so it cannot be guessed
+// byte by byte from response times
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    return a
+        .iter()
+        .zip(b)
+        .fold(0, |difference, (x, y)| difference | (x ^ y))
+        == 0;
+}
@@ -0,0 +1,222 @@
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower_layer::layer_fn;
+use tower_service::Service;
+
+use crate::http_connect;
+use crate::tls;
+
+pub const DEFAULT_POOL_MAX_IDLE: usize = 8;
+pub const DEFAULT_POOL_IDLE_TIMEOUT_MS: u64 = 4000; // Below the 5 s Rocket keeps idle connections open
+pub const DEFAULT_POOL_MAX_PEERS: usize = 64;
+
+static POOL: OnceLock<Mutex<Pool>> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug)]
+pub struct PoolSettings {
+    pub max_idle: usize, // Idle connections kept per peer, 0 opens a new connection for every request
+    pub idle_timeout: Duration, // Idle connections, and peers without requests, are dropped after this long
+    pub max_peers: usize, // The least recently used peer is dropped to make room for a new one
+}
+
+// Reuse of the connections to one node. A retried request is counted once.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct PeerStats {
+    pub peer: String,
+    pub requests: u64,
+    pub connections: u64, // Newly opened, every other request went over a kept connection
+    pub reuse_rate: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct PoolStats {
+    pub peers: Vec<PeerStats>,
+    pub evicted_peers: u64,
+    pub requests: u64, // Totals include peers that have since been evicted
+    pub connections: u64,
+    pub reuse_rate: f64,
+}
+
+struct Peer {
+    client: reqwest::Client,
+    requests: u64,
+    connections: Arc<AtomicU64>, // Counted by the connector of the client
+    last_used: Instant,
+}
+
+struct Pool {
+    settings: PoolSettings,
+    connect_timeout: Duration,
+    peers: HashMap<String, Peer>,
+    evicted_peers: u64,
+    evicted_requests: u64,
+    evicted_connections: u64,
+}
+
+impl Pool {
+    fn evict(&mut self, peer_id: &str) {
+        if let Some(peer) = self.peers.remove(peer_id) {
+            self.evicted_peers += 1;
+            self.evicted_requests += peer.requests;
+            self.evicted_connections += peer.connections.load(Ordering::Relaxed);
+        }
+    }
+
+    // Nodes that left or crashed are not asked again, so their clients would otherwise stay forever
+    fn evict_idle(&mut self, now: Instant) {
+        let idle_timeout = self.settings.idle_timeout;
+        let idle: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|(_peer_id, peer)| now.duration_since(peer.last_used) > idle_timeout)
+            .map(|(peer_id, _peer)| peer_id.clone())
+            .collect();
+
+        for peer_id in idle {
+            self.evict(&peer_id);
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self
+            .peers
+            .iter()
+            .min_by_key(|(_peer_id, peer)| peer.last_used)
+            .map(|(peer_id, _peer)| peer_id.clone());
+
+        if let Some(peer_id) = oldest {
+            self.evict(&peer_id);
+        }
+    }
+}
+
+fn pool() -> &'static Mutex<Pool> {
+    POOL.get_or_init(|| {
+        let settings = http_connect::settings();
+        Mutex::new(Pool {
+            settings: settings.pool,
+            connect_timeout: settings.connect_timeout,
+            peers: HashMap::new(),
+            evicted_peers: 0,
+            evicted_requests: 0,
+            evicted_connections: 0,
+        })
+    })
+}
+
+// Client keeping its connections to the node open between requests. Every node gets a client of its
+// own, so reuse can be counted per node.
+pub fn client_for(hostname: &str, port: u16) -> reqwest::Client {
+    let mut pool = pool().lock().expect("Mutex is poisoned");
+    let now = Instant::now();
+    let peer_id = format!("{}:{}", hostname, port);
+
+    pool.evict_idle(now);
+    if !pool.peers.contains_key(&peer_id) && pool.peers.len() >= pool.settings.max_peers {
+        pool.evict_least_recently_used();
+    }
+
+    let (settings, connect_timeout) = (pool.settings, pool.connect_timeout);
+    let peer = pool
+        .peers
+        .entry(peer_id)
+        .or_insert_with(|| new_peer(settings, connect_timeout));
+    peer.requests += 1;
+    peer.last_used = now;
+
+    return peer.client.clone();
+}
+
+fn new_peer(settings: PoolSettings, connect_timeout: Duration) -> Peer {
+    let connections = Arc::new(AtomicU64::new(0));
+    let counter = connections.clone();
+
+    let builder = reqwest::Client::builder()
+        .pool_max_idle_per_host(settings.max_idle)
+        .pool_idle_timeout(settings.idle_timeout)
+        .connect_timeout(connect_timeout)
+        .connector_layer(layer_fn(move |inner| CountConnections {
+            inner,
+            connections: counter.clone(),
+        }));
+    let client = tls::configure_client(builder)
+        .build()
+        .expect("Unable to build HTTP client.");
+
+    return Peer {
+        client,
+        requests: 0,
+        connections,
+        last_used: Instant::now(),
+    };
+}
+
+pub fn stats() -> PoolStats {
+    let pool = pool().lock().expect("Mutex is poisoned");
+
+    let mut peers: Vec<PeerStats> = pool
+        .peers
+        .iter()
+        .map(|(peer_id, peer)| {
+            let connections = peer.connections.load(Ordering::Relaxed);
+            PeerStats {
+                peer: peer_id.clone(),
+                requests: peer.requests,
+                connections,
+                reuse_rate: reuse_rate(peer.requests, connections),
+            }
+        })
+        .collect();
+    peers.sort_by(|a, b| a.peer.cmp(&b.peer));
+
+    let requests = pool.evicted_requests + peers.iter().map(|peer| peer.requests).sum::<u64>();
+    let connections =
+        pool.evicted_connections + peers.iter().map(|peer| peer.connections).sum::<u64>();
+
+    return PoolStats {
+        peers,
+        evicted_peers: pool.evicted_peers,
+        requests,
+        connections,
+        reuse_rate: reuse_rate(requests, connections),
+    };
+}
+
+// Share of requests that did not need a connection of their own
+fn reuse_rate(requests: u64, connections: u64) -> f64 {
+    if requests == 0 {
+        return 0.0;
+    }
+    return requests.saturating_sub(connections) as f64 / requests as f64;
+}
+
+// Wraps the connector of a client, counting every connection it opens
+#[derive(Clone)]
+struct CountConnections<S> {
+    inner: S,
+    connections: Arc<AtomicU64>,
+}
+
+impl<S, R> Service<R> for CountConnections<S>
+where
+    S: Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.inner.call(request)
+    }
+}
@@ -0,0 +1,319 @@
+use rocket::fairing::AdHoc;
+use rocket::serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::identifier::Identifier;
+use crate::node_config::NodeConfig;
+use crate::stabilization::{is_same_node, spawn_periodic};
+use crate::{
+    build_successor_list, fetch_owned_keys_from_replicas, http_connect, is_node_alive,
+    set_successor, Node,
+};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "rocket::serde")]
+pub enum NeighbourStatus {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct NeighbourHealth {
+    pub node: Node,
+    pub missed_heartbeats: u32,
+    pub status: NeighbourStatus,
+}
+
+#[derive(Clone, Copy)]
+pub struct FailureDetectorSettings {
+    pub heartbeat_interval: Option<Duration>, // None disables the detector
+    pub suspect_after: u32,                   // Missed heartbeats before a neighbour is suspected
+    pub dead_after: u32,                      // Missed heartbeats before a neighbour is spliced out
+}
+
+// Fairing starting the heartbeat task once Rocket is listening
+pub fn fairing(settings: FailureDetectorSettings) -> AdHoc {
+    AdHoc::on_liftoff("Failure detector", move |rocket| {
+        Box::pin(async move {
+            let node_config = rocket
+                .state::<Arc<RwLock<NodeConfig>>>()
+                .expect("NodeConfig is not managed")
+                .clone();
+
+            if let Some(interval) = settings.heartbeat_interval {
+                println!("Sending heartbeats every {:?}", interval);
+                spawn_periodic(node_config, interval, move |node_config| {
+                    heartbeat(node_config, settings)
+                });
+            }
+        })
+    })
+}
+
+pub fn node_id(node: &Node) -> String {
+    format!("{}:{}", node.hostname, node.port)
+}
+
+// Pings our precessor and successor on /helloworld, and repairs the ring around any that stopped answering
+async fn heartbeat(
+    node_config: Arc<RwLock<NodeConfig>>,
+    settings: FailureDetectorSettings,
+) -> Result<(), String> {
+    let (local, successor, precessor) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+        if config.is_crashed() || !config.connected {
+            return Ok(());
+        }
+        (
+            config.local.clone(),
+            config.successor.clone(),
+            config.precessor.clone(),
+        )
+    };
+
+    let mut neighbours: Vec<Node> = successor
+        .iter()
+        .chain(precessor.iter())
+        .filter(|node| !is_same_node(node, &local))
+        .cloned()
+        .collect();
+
+    // In a ring of two our successor is also our precessor, and should only be pinged once
+    neighbours.dedup_by(|a, b| is_same_node(a, b));
+
+    // Ping without holding the lock, a hanging neighbour must not block our own handlers
+    let mut answers: Vec<(Node, bool)> = vec![];
+    for node in neighbours {
+        let alive = is_node_alive(&node).await;
+        answers.push((node, alive));
+    }
+
+    let mut dead_neighbours: Vec<Node> = vec![];
+    {
+        let mut config = node_config.write().expect("RWLock is poisoned");
+
+        // Forget neighbours we no longer have
+        let current: Vec<String> = answers.iter().map(|(node, _alive)| node_id(node)).collect();
+        config
+            .neighbour_health
+            .retain(|id, _health| current.contains(id));
+
+        for (node, alive) in answers {
+            let health = config
+                .neighbour_health
+                .entry(node_id(&node))
+                .or_insert(NeighbourHealth {
+                    node: node.clone(),
+                    missed_heartbeats: 0,
+                    status: NeighbourStatus::Alive,
+                });
+
+            health.missed_heartbeats = if alive {
+                0
+            } else {
+                health.missed_heartbeats + 1
+            };
+
+            let status = if health.missed_heartbeats >= settings.dead_after {
+                NeighbourStatus::Dead
+            } else if health.missed_heartbeats >= settings.suspect_after {
+                NeighbourStatus::Suspect
+            } else {
+                NeighbourStatus::Alive
+            };
+
+            if status != health.status {
+                println!("Neighbour {} is now {:?}", node_id(&node), status);
+                health.status = status;
+            }
+
+            // Repair is retried on every heartbeat until the dead neighbour is gone
+            if status == NeighbourStatus::Dead {
+                dead_neighbours.push(node);
+            }
+        }
+    }
+
+    // A dead precessor is kept until the next node notifies us, which then replaces it regardless of position
+    for dead in dead_neighbours {
+        if successor
+            .as_ref()
+            .is_some_and(|node| is_same_node(node, &dead))
+        {
+            splice_out_successor(&node_config, &dead).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// Replaces the dead successor with the closest live node we know of after it. Our range grows to cover
+// the range of the dead node, and its keys are fetched from the replicas that now follow us.
+async fn splice_out_successor(node_config: &RwLock<NodeConfig>, dead: &Node) -> Result<(), String> {
+    let (local, precessor, mut candidates) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+        let candidates: Vec<Node> = config
+            .successor_list
+            .iter()
+            .chain(config.finger_table.iter())
+            .filter(|node| !is_same_node(node, dead) && !is_same_node(node, &config.local))
+            .cloned()
+            .collect();
+        (
+            config.local.clone(),
+            live_precessor(&config, dead),
+            candidates,
+        )
+    };
+
+    // Closest clockwise from us first
+    candidates.sort_by_key(|node| node.position - local.position);
+
+    let mut new_successor: Option<Node> = None;
+    for node in candidates {
+        if let Ok(response) =
+            http_connect::get_from_node(&node.hostname, node.port, "ring/local").await
+        {
+            if let Ok(current) = response.json::<Node>() {
+                new_successor = Some(current);
+                break;
+            }
+        }
+    }
+
+    // Without a candidate of our own, the rest of the ring is still there behind our precessor. Only
+    // when the precessor is gone as well are we the last node left.
+    if new_successor.is_none() {
+        if let Some(precessor) = precessor {
+            new_successor = find_node_after(dead, &local, precessor).await;
+            if new_successor.is_none() {
+                return Err(format!(
+                    "Could not find the node after dead successor {}, retrying on the next heartbeat",
+                    node_id(dead)
+                ));
+            }
+        }
+    }
+
+    if let Some(new_successor) = new_successor.as_ref() {
+        if let Err(_err) = http_connect::write_json_to_node(
+            http_connect::WriteOperations::Put,
+            &new_successor.hostname,
+            new_successor.port,
+            "ring/precessor",
+            &local,
+        )
+        .await
+        {
+            return Err(format!(
+                "Could not set precessor of new successor {}",
+                node_id(new_successor)
+            ));
+        }
+    }
+
+    let wanted = {
+        let mut config = node_config.write().expect("RWLock is poisoned");
+
+        // The ring may have been repaired by a join or stabilize while we were looking for a replacement
+        if !config
+            .successor
+            .as_ref()
+            .is_some_and(|node| is_same_node(node, dead))
+        {
+            return Ok(());
+        }
+
+        match new_successor.clone() {
+            None => {
+                // Nobody else is left, our precessor is gone too, so we hold the whole ring
+                println!(
+                    "No live node left after {}, taking over the whole ring",
+                    node_id(dead)
+                );
+                config.local.range = Identifier::whole_ring();
+                config.successor = Some(config.local.clone());
+                config.precessor = Some(config.local.clone());
+                config.successor_list.clear();
+            }
+            Some(new_successor) => {
+                println!(
+                    "Spliced out dead successor {}, new successor is {}",
+                    node_id(dead),
+                    node_id(&new_successor)
+                );
+                set_successor(&mut config, new_successor);
+            }
+        }
+
+        config.replication_factor() - 1
+    };
+
+    if let Some(new_successor) = new_successor {
+        let successor_list = build_successor_list(&local, new_successor, wanted)
+            .await
+            .unwrap_or_default();
+        node_config
+            .write()
+            .expect("RWLock is poisoned")
+            .successor_list = successor_list;
+    }
+
+    let fetched = fetch_owned_keys_from_replicas(node_config).await;
+    println!("Fetched {} keys of the dead node from replicas", fetched);
+
+    Ok(())
+}
+
+// Our precessor, unless it is ourselves, the dead successor, or dead too
+fn live_precessor(config: &NodeConfig, dead: &Node) -> Option<Node> {
+    let precessor = config.precessor.as_ref()?;
+    if is_same_node(precessor, &config.local) || is_same_node(precessor, dead) {
+        return None;
+    }
+
+    let status = config
+        .neighbour_health
+        .get(&node_id(precessor))
+        .map(|health| health.status);
+    if status == Some(NeighbourStatus::Dead) {
+        return None;
+    }
+    return Some(precessor.clone());
+}
+
+// Walks the ring backwards from our precessor to the first live node after the dead one, the node whose
+// own precessor is the dead node, us, or does not answer
+async fn find_node_after(dead: &Node, local: &Node, precessor: Node) -> Option<Node> {
+    let mut visited: Vec<String> = vec![];
+    let mut last_live: Option<Node> = None;
+    let mut current = precessor;
+
+    while !visited.contains(&node_id(&current)) {
+        visited.push(node_id(&current));
+
+        let response =
+            match http_connect::get_from_node(&current.hostname, current.port, "ring/precessor")
+                .await
+            {
+                Err(_err) => return last_live,
+                Ok(response) => response,
+            };
+        let previous = match response.json::<Node>() {
+            Err(_err) => return last_live,
+            Ok(previous) => previous,
+        };
+
+        if is_same_node(&previous, dead) || is_same_node(&previous, local) {
+            return Some(current);
+        }
+        last_live = Some(current);
+        current = previous;
+    }
+
+    // The walk went around without reaching the dead node
+    return None;
+}
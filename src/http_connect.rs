@@ -1,48 +1,270 @@
-use minreq::Response;
+use http::Method;
 use rocket::serde;
+use rocket::serde::json;
+use rocket::tokio;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::str::Utf8Error;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::auth;
+use crate::connection_pool::{
+    self, PoolSettings, DEFAULT_POOL_IDLE_TIMEOUT_MS, DEFAULT_POOL_MAX_IDLE, DEFAULT_POOL_MAX_PEERS,
+};
+use crate::membership;
+use crate::metrics;
+use crate::rpc::{self, RpcCall, Transport, DEFAULT_RPC_PORT_OFFSET};
+use crate::tls;
+
+pub const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 1000;
+pub const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 10000;
+pub const DEFAULT_RETRIES: u32 = 2;
+pub const DEFAULT_RETRY_BACKOFF_MS: u64 = 100;
+
+static SETTINGS: OnceLock<ClientSettings> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClientSettings {
+    pub connect_timeout: Duration,
+    pub request: RequestSettings, // Used by every call that does not bring its own
+    pub pool: PoolSettings,
+    pub transport: Transport,
+    pub rpc_port_offset: u16, // A node listens for RPC on its HTTP port plus this offset
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RequestSettings {
+    pub timeout: Duration, // Whole request, from connecting until the body is read
+    pub retries: u32, // Extra attempts after a timeout or failed connection, only for idempotent requests
+    pub backoff: Duration, // Wait before the first retry, doubled for every following one
+    pub retry_timeouts: bool, // Whether an attempt that timed out is made again, or only failed connections
+}
+
+// Extra headers and settings of a single request
+#[derive(Clone, Copy)]
+pub struct CallOptions<'a> {
+    pub headers: &'a [(&'a str, &'a str)],
+    pub request: RequestSettings,
+}
+
+impl Default for CallOptions<'_> {
+    fn default() -> Self {
+        CallOptions {
+            headers: &[],
+            request: settings().request,
+        }
+    }
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            connect_timeout: Duration::from_millis(DEFAULT_CONNECT_TIMEOUT_MS),
+            request: RequestSettings {
+                timeout: Duration::from_millis(DEFAULT_REQUEST_TIMEOUT_MS),
+                retries: DEFAULT_RETRIES,
+                backoff: Duration::from_millis(DEFAULT_RETRY_BACKOFF_MS),
+                retry_timeouts: true,
+            },
+            pool: PoolSettings {
+                max_idle: DEFAULT_POOL_MAX_IDLE,
+                idle_timeout: Duration::from_millis(DEFAULT_POOL_IDLE_TIMEOUT_MS),
+                max_peers: DEFAULT_POOL_MAX_PEERS,
+            },
+            transport: Transport::Http,
+            rpc_port_offset: DEFAULT_RPC_PORT_OFFSET,
+        }
+    }
+}
+
+// Timeouts and retries of the process. Set once at startup, before the first request is sent.
+pub fn settings() -> ClientSettings {
+    *SETTINGS.get_or_init(ClientSettings::default)
+}
+
+pub fn set_settings(settings: ClientSettings) -> Result<(), String> {
+    SETTINGS
+        .set(settings)
+        .map_err(|_settings| String::from("Client settings have already been set"))
+}
+
+// Response of another node, read in full so it can be inspected after the request has finished
+#[derive(Debug)]
+pub struct Response {
+    pub status_code: i32,
+    pub headers: HashMap<String, String>, // Names in lowercase
+    body: Vec<u8>,
+    transport: Transport, // Values are JSON over HTTP and MessagePack over the binary transport
+}
+
+impl Response {
+    // The body as a value, whichever transport it came over
+    pub fn json<T>(&self) -> Result<T, NodeConnectionError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        match self.transport {
+            Transport::Http => json::serde_json::from_slice(&self.body)
+                .map_err(|err| NodeConnectionError::Decode(err.to_string())),
+            Transport::Binary => rmp_serde::from_slice(&self.body)
+                .map_err(|err| NodeConnectionError::Decode(err.to_string())),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.body)
+    }
+}
 
 #[derive(Debug)]
-pub struct NodeConnectionError {
-    pub connection_established: bool,
-    pub http_response: Option<Response>,
+pub enum NodeConnectionError {
+    Timeout,              // No answer within the timeout
+    Refused,              // No connection could be made, most likely the node is not running
+    Broken(String),       // The connection failed after the request was sent
+    BadStatus(Response),  // The node answered with anything but 200 OK
+    Decode(String),       // The body could not be read or parsed
+    OtherNetwork(String), // The node is in another network than the sender, and says which
+}
+
+impl NodeConnectionError {
+    pub fn status_code(&self) -> Option<i32> {
+        match self {
+            NodeConnectionError::BadStatus(response) => Some(response.status_code),
+            _ => None,
+        }
+    }
+
+    // Name of the kind of error, as used in metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NodeConnectionError::Timeout => "timeout",
+            NodeConnectionError::Refused => "refused",
+            NodeConnectionError::Broken(_) => "broken",
+            NodeConnectionError::BadStatus(_) => "bad_status",
+            NodeConnectionError::Decode(_) => "decode",
+            NodeConnectionError::OtherNetwork(_) => "other_network",
+        }
+    }
+
+    // Whether the request may succeed when sent again
+    fn is_transient(&self) -> bool {
+        match self {
+            NodeConnectionError::Timeout
+            | NodeConnectionError::Refused
+            | NodeConnectionError::Broken(_) => true,
+            NodeConnectionError::BadStatus(_)
+            | NodeConnectionError::Decode(_)
+            | NodeConnectionError::OtherNetwork(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for NodeConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NodeConnectionError::Timeout => write!(f, "timed out"),
+            NodeConnectionError::Refused => write!(f, "connection refused"),
+            NodeConnectionError::Broken(reason) => write!(f, "connection broken: {}", reason),
+            NodeConnectionError::BadStatus(response) => {
+                write!(f, "answered with status {}", response.status_code)
+            }
+            NodeConnectionError::Decode(reason) => write!(f, "unreadable response: {}", reason),
+            NodeConnectionError::OtherNetwork(reason) => write!(f, "other network: {}", reason),
+        }
+    }
 }
 
+impl From<reqwest::Error> for NodeConnectionError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            return NodeConnectionError::Timeout;
+        }
+        if err.is_connect() {
+            return NodeConnectionError::Refused;
+        }
+        if err.is_decode() || err.is_body() {
+            return NodeConnectionError::Decode(err.to_string());
+        }
+        return NodeConnectionError::Broken(err.to_string());
+    }
+}
+
+#[derive(Clone, Copy)]
 pub enum WriteOperations {
     Post,
     Put,
     Delete,
 }
 
+impl WriteOperations {
+    // Sending it twice has the same effect as sending it once, so it can be retried
+    fn is_idempotent(&self) -> bool {
+        match self {
+            WriteOperations::Post => false,
+            WriteOperations::Put | WriteOperations::Delete => true,
+        }
+    }
+
+    // A DELETE that timed out may have removed the key already, and trying it again would then answer
+    // 404 for a delete that worked. It is only tried again when the node could not be reached.
+    fn request_settings(&self, request_settings: RequestSettings) -> RequestSettings {
+        match self {
+            WriteOperations::Delete => RequestSettings {
+                retry_timeouts: false,
+                ..request_settings
+            },
+            WriteOperations::Post | WriteOperations::Put => request_settings,
+        }
+    }
+
+    fn method(&self) -> Method {
+        match self {
+            WriteOperations::Post => Method::POST,
+            WriteOperations::Put => Method::PUT,
+            WriteOperations::Delete => Method::DELETE,
+        }
+    }
+}
+
 pub fn check_if_node_is_connected() {}
 
-pub fn get_from_node(
+// HTTPS once TLS is set up, as nodes then no longer answer plain HTTP
+fn node_url(hostname: &str, port: u16, path: &str) -> String {
+    return format!("{}://{}:{}/{}", tls::scheme(), hostname, port, path);
+}
+
+pub async fn get_from_node(
     hostname: &str,
     port: u16,
     path: &str,
 ) -> Result<Response, NodeConnectionError> {
-    let request_uri = format!("http://{}:{}/{}", hostname, port, path);
-
-    let received_response = match minreq::get(request_uri).send() {
-        Err(_err) => {
-            return Err(NodeConnectionError {
-                connection_established: false,
-                http_response: None,
-            });
-        }
-        Ok(response) => response,
-    };
+    return get_from_node_with_options(hostname, port, path, CallOptions::default()).await;
+}
+
+// Same as get_from_node, with extra headers, and its own timeout and retries in place of those of the process
+pub async fn get_from_node_with_options(
+    hostname: &str,
+    port: u16,
+    path: &str,
+    options: CallOptions<'_>,
+) -> Result<Response, NodeConnectionError> {
+    if let Some(call) = binary_call(&Method::GET, path, options.headers) {
+        return send_binary(hostname, port, call, vec![], options.request, true).await;
+    }
 
-    if received_response.status_code != 200 {
-        return Err(NodeConnectionError {
-            connection_established: true,
-            http_response: Some(received_response),
-        });
+    let request_uri = node_url(hostname, port, path);
+
+    let mut request = connection_pool::client_for(hostname, port).get(request_uri);
+    for (name, value) in options.headers {
+        request = request.header(*name, *value);
     }
 
-    return Ok(received_response);
+    return send(request, options.request, true).await;
 }
 
-pub fn write_body_to_node<T>(
+pub async fn write_body_to_node<T>(
     operation: WriteOperations,
     hostname: &str,
     port: u16,
@@ -53,76 +275,255 @@ pub fn write_body_to_node<T>(
 where
     T: Into<Vec<u8>>,
 {
-    let request_uri = format!("http://{}:{}/{}", hostname, port, path);
+    return write_body_to_node_with_options(
+        operation,
+        hostname,
+        port,
+        path,
+        content_type,
+        body,
+        CallOptions::default(),
+    )
+    .await;
+}
 
-    let func = match operation {
-        WriteOperations::Post => minreq::post,
-        WriteOperations::Put => minreq::put,
-        WriteOperations::Delete => minreq::delete,
-    };
+pub async fn write_body_to_node_with_options<T>(
+    operation: WriteOperations,
+    hostname: &str,
+    port: u16,
+    path: &str,
+    content_type: &str,
+    body: T,
+    options: CallOptions<'_>,
+) -> Result<Response, NodeConnectionError>
+where
+    T: Into<Vec<u8>>,
+{
+    let idempotent = operation.is_idempotent();
+    let request_settings = operation.request_settings(options.request);
+    if let Some(call) = binary_call(&operation.method(), path, options.headers) {
+        return send_binary(
+            hostname,
+            port,
+            call,
+            body.into(),
+            request_settings,
+            idempotent,
+        )
+        .await;
+    }
 
-    let received_response = match func(request_uri)
-        .with_body(body)
-        .with_header("Content-Type", content_type)
-        .send()
-    {
-        Err(_err) => {
-            return Err(NodeConnectionError {
-                connection_established: false,
-                http_response: None,
-            });
-        }
-        Ok(response) => response,
-    };
+    let request_uri = node_url(hostname, port, path);
 
-    if received_response.status_code != 200 {
-        return Err(NodeConnectionError {
-            connection_established: true,
-            http_response: Some(received_response),
-        });
+    let mut request = write_request(operation, hostname, port, request_uri)
+        .body(body.into())
+        .header("Content-Type", content_type);
+    for (name, value) in options.headers {
+        request = request.header(*name, *value);
     }
 
-    return Ok(received_response);
+    return send(request, request_settings, idempotent).await;
+}
+
+pub async fn write_json_to_node<T>(
+    operation: WriteOperations,
+    hostname: &str,
+    port: u16,
+    path: &str,
+    content: T,
+) -> Result<Response, NodeConnectionError>
+where
+    T: serde::ser::Serialize,
+{
+    return write_json_to_node_with_options(
+        operation,
+        hostname,
+        port,
+        path,
+        content,
+        CallOptions::default(),
+    )
+    .await;
 }
 
-pub fn write_json_to_node<T>(
+pub async fn write_json_to_node_with_options<T>(
     operation: WriteOperations,
     hostname: &str,
     port: u16,
     path: &str,
     content: T,
+    options: CallOptions<'_>,
 ) -> Result<Response, NodeConnectionError>
 where
     T: serde::ser::Serialize,
 {
-    let request_uri = format!("http://{}:{}/{}", hostname, port, path);
+    let idempotent = operation.is_idempotent();
+    let request_settings = operation.request_settings(options.request);
+    if let Some(call) = binary_call(&operation.method(), path, options.headers) {
+        let body = rmp_serde::to_vec(&content).expect("Could not serialize content.");
+        return send_binary(hostname, port, call, body, request_settings, idempotent).await;
+    }
+
+    let request_uri = node_url(hostname, port, path);
+
+    let mut request = write_request(operation, hostname, port, request_uri)
+        .body(json::serde_json::to_vec(&content).expect("Could not serialize content."))
+        .header("Content-Type", "application/json");
+    for (name, value) in options.headers {
+        request = request.header(*name, *value);
+    }
+
+    return send(request, request_settings, idempotent).await;
+}
+
+fn write_request(
+    operation: WriteOperations,
+    hostname: &str,
+    port: u16,
+    request_uri: String,
+) -> reqwest::RequestBuilder {
+    let client = connection_pool::client_for(hostname, port);
+
+    match operation {
+        WriteOperations::Post => client.post(request_uri),
+        WriteOperations::Put => client.put(request_uri),
+        WriteOperations::Delete => client.delete(request_uri),
+    }
+}
 
-    let func = match operation {
-        WriteOperations::Post => minreq::post,
-        WriteOperations::Put => minreq::put,
-        WriteOperations::Delete => minreq::delete,
+// The binary form of the request, when that is the transport to use and the request has one
+fn binary_call(method: &Method, path: &str, headers: &[(&str, &str)]) -> Option<RpcCall> {
+    if settings().transport != Transport::Binary || !headers.is_empty() {
+        return None;
+    }
+    return rpc::call_for(method, path);
+}
+
+async fn send(
+    request: reqwest::RequestBuilder,
+    request_settings: RequestSettings,
+    idempotent: bool,
+) -> Result<Response, NodeConnectionError> {
+    // The network token goes along with every request, so ring-internal routes let it through
+    let mut request = match auth::bearer() {
+        Some(bearer) => request.header(auth::AUTHORIZATION_HEADER, bearer),
+        None => request,
     };
+    for (name, value) in membership::sender_headers() {
+        request = request.header(name, value);
+    }
 
-    let received_response = match func(request_uri)
-        .with_json(&content)
-        .expect("Could not serialize content.")
-        .send()
-    {
-        Err(_err) => {
-            return Err(NodeConnectionError {
-                connection_established: false,
-                http_response: None,
-            });
-        }
-        Ok(response) => response,
+    return with_retries(request_settings, idempotent, || {
+        // Bodies are always in memory, so the request can be cloned
+        let attempt_request = request.try_clone().expect("Request body cannot be cloned");
+        send_once(attempt_request.timeout(request_settings.timeout))
+    })
+    .await;
+}
+
+async fn send_binary(
+    hostname: &str,
+    port: u16,
+    call: RpcCall,
+    body: Vec<u8>,
+    request_settings: RequestSettings,
+    idempotent: bool,
+) -> Result<Response, NodeConnectionError> {
+    let Some(rpc_port) = port.checked_add(settings().rpc_port_offset) else {
+        return Err(NodeConnectionError::Refused);
+    };
+
+    return with_retries(request_settings, idempotent, || async {
+        let (status, headers, body) =
+            rpc::send(hostname, rpc_port, &call, &body, request_settings.timeout).await?;
+        let received_response = Response {
+            status_code: status as i32,
+            headers,
+            body,
+            transport: Transport::Binary,
+        };
+
+        return status_error(received_response);
+    })
+    .await;
+}
+
+// Makes attempts, trying again with a growing pause while the node cannot be reached. Requests that
+// are not idempotent are sent only once, as the node may have acted on an attempt that timed out.
+// Timeouts are retried only when the settings allow it.
+async fn with_retries<F, R>(
+    request_settings: RequestSettings,
+    idempotent: bool,
+    send_attempt: F,
+) -> Result<Response, NodeConnectionError>
+where
+    F: Fn() -> R,
+    R: Future<Output = Result<Response, NodeConnectionError>>,
+{
+    let retries = if idempotent {
+        request_settings.retries
+    } else {
+        0
     };
+    let mut backoff = request_settings.backoff;
+    let mut attempt = 0;
 
-    if received_response.status_code != 200 {
-        return Err(NodeConnectionError {
-            connection_established: true,
-            http_response: Some(received_response),
-        });
+    loop {
+        match send_attempt().await {
+            Err(err)
+                if err.is_transient()
+                    && attempt < retries
+                    && (request_settings.retry_timeouts
+                        || !matches!(err, NodeConnectionError::Timeout)) =>
+            {
+                attempt += 1;
+                println!("Request failed ({}), retry {} of {}", err, attempt, retries);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(err) => {
+                metrics::record_connection_failure(&err);
+                return Err(err);
+            }
+            result => return result,
+        }
     }
+}
 
-    return Ok(received_response);
+// Sends the request and reads the whole response, anything but 200 OK counts as an error
+async fn send_once(request: reqwest::RequestBuilder) -> Result<Response, NodeConnectionError> {
+    let received = request.send().await?;
+
+    let status_code = received.status().as_u16() as i32;
+    let headers = received
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| {
+            let value = value.to_str().ok()?;
+            Some((name.as_str().to_lowercase(), String::from(value)))
+        })
+        .collect();
+
+    let body = received.bytes().await?.to_vec();
+
+    let received_response = Response {
+        status_code,
+        headers,
+        body,
+        transport: Transport::Http,
+    };
+
+    return status_error(received_response);
+}
+
+// Anything but 200 OK is an error, and 421 Misdirected Request means the node is in another network
+fn status_error(received_response: Response) -> Result<Response, NodeConnectionError> {
+    match received_response.status_code {
+        200 => return Ok(received_response),
+        421 => {
+            let reason = received_response.as_str().unwrap_or("").to_string();
+            return Err(NodeConnectionError::OtherNetwork(reason));
+        }
+        _ => return Err(NodeConnectionError::BadStatus(received_response)),
+    }
 }
@@ -0,0 +1,453 @@
+use rocket::request::FromParam;
+use rocket::serde::de::{self, Visitor};
+use rocket::serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+pub const DEFAULT_RING_BITS: u32 = 16;
+pub const MAX_RING_BITS: u32 = 160; // Width of a SHA-1 digest
+
+const LIMBS: usize = 3; // 64 bit limbs, least significant first, enough to hold 160 bits
+
+static RING_BITS: OnceLock<u32> = OnceLock::new();
+
+// Number of bits in an identifier, the ring has 2^bits positions. Set once at startup, all nodes in a
+// network have to use the same width.
+pub fn ring_bits() -> u32 {
+    *RING_BITS.get().unwrap_or(&DEFAULT_RING_BITS)
+}
+
+pub fn set_ring_bits(bits: u32) -> Result<(), String> {
+    if bits == 0 || bits > MAX_RING_BITS {
+        return Err(format!(
+            "Ring bits must be between 1 and {}, got {}",
+            MAX_RING_BITS, bits
+        ));
+    }
+
+    RING_BITS
+        .set(bits)
+        .map_err(|_bits| String::from("Ring bits have already been set"))
+}
+
+// Position or distance on the ring. All arithmetic wraps around modulo 2^ring_bits().
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Identifier {
+    limbs: [u64; LIMBS],
+}
+
+impl Identifier {
+    pub fn zero() -> Self {
+        Identifier { limbs: [0; LIMBS] }
+    }
+
+    pub fn from_u64(value: u64) -> Self {
+        Identifier {
+            limbs: [value, 0, 0],
+        }
+        .masked()
+    }
+
+    // The last position on the ring. As a range it leaves out only the position before its start.
+    pub fn max() -> Self {
+        Identifier {
+            limbs: [u64::MAX; LIMBS],
+        }
+        .masked()
+    }
+
+    // Range of a node that is its own successor, reaching all the way around the ring back to the node,
+    // which wraps to a length of zero
+    pub fn whole_ring() -> Self {
+        Identifier::zero()
+    }
+
+    // Whether this location lies in the range starting at position
+    pub fn is_in_range(self, position: Identifier, range: Identifier) -> bool {
+        // Subtraction wraps around the ring, so this also covers ranges wrapping past zero
+        range == Identifier::whole_ring() || self - position < range
+    }
+
+    // Length of a range, to compare or split it by. The whole ring does not fit, so it counts as the
+    // longest range short of it.
+    pub fn range_length(self) -> Self {
+        if self == Identifier::whole_ring() {
+            return Identifier::max();
+        }
+        self
+    }
+
+    // 2^exponent, wrapping to zero once it no longer fits on the ring
+    pub fn power_of_two(exponent: u32) -> Self {
+        let mut limbs = [0; LIMBS];
+        if (exponent as usize) < LIMBS * 64 {
+            limbs[exponent as usize / 64] = 1 << (exponent % 64);
+        }
+        Identifier { limbs }.masked()
+    }
+
+    // The first ring_bits() bits of the digest, read as a big-endian number
+    pub fn from_digest(digest: &[u8]) -> Self {
+        let mut limbs = [0; LIMBS];
+        for (index, byte) in digest.iter().take(MAX_RING_BITS as usize / 8).enumerate() {
+            // Byte 0 is the most significant of the 160 bits
+            let bit = MAX_RING_BITS as usize - 8 * (index + 1);
+            limbs[bit / 64] |= (*byte as u64) << (bit % 64);
+        }
+
+        Identifier { limbs }.shifted_right(MAX_RING_BITS - ring_bits())
+    }
+
+    // One n-th of the ring rounded down, for at least two parts as the whole ring does not fit
+    pub fn ring_fraction(parts: u64) -> Self {
+        // The ring size 2^bits is one more than the largest identifier
+        let mut fraction = Identifier::max();
+        let remainder = fraction.divide_by(parts);
+        if remainder + 1 == parts {
+            fraction = fraction + Identifier::from_u64(1);
+        }
+        fraction
+    }
+
+    pub fn half(self) -> Self {
+        self.shifted_right(1)
+    }
+
+    // Share of the whole ring, between 0 and 1. Only approximate for rings wider than 53 bits.
+    pub fn as_ring_share(&self) -> f64 {
+        let value = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0.0, |value, limb| value * 2f64.powi(64) + *limb as f64);
+        value / 2f64.powi(ring_bits() as i32)
+    }
+
+    fn shifted_right(self, shift: u32) -> Self {
+        let mut limbs = [0; LIMBS];
+        let limb_shift = shift as usize / 64;
+        let bit_shift = shift % 64;
+
+        for (index, limb) in limbs.iter_mut().enumerate() {
+            let source = index + limb_shift;
+            if source >= LIMBS {
+                break;
+            }
+            *limb = self.limbs[source] >> bit_shift;
+            if bit_shift > 0 && source + 1 < LIMBS {
+                *limb |= self.limbs[source + 1] << (64 - bit_shift);
+            }
+        }
+
+        Identifier { limbs }
+    }
+
+    fn masked(mut self) -> Self {
+        let bits = ring_bits() as usize;
+        for (index, limb) in self.limbs.iter_mut().enumerate() {
+            let low_bit = index * 64;
+            if bits <= low_bit {
+                *limb = 0;
+            } else if bits < low_bit + 64 {
+                *limb &= (1 << (bits - low_bit)) - 1;
+            }
+        }
+        self
+    }
+
+    fn is_zero(&self) -> bool {
+        self.limbs.iter().all(|limb| *limb == 0)
+    }
+
+    // Divides by a small number in place, returning the remainder
+    fn divide_by(&mut self, divisor: u64) -> u64 {
+        let mut remainder: u128 = 0;
+        for limb in self.limbs.iter_mut().rev() {
+            let value = (remainder << 64) | *limb as u128;
+            *limb = (value / divisor as u128) as u64;
+            remainder = value % divisor as u128;
+        }
+        remainder as u64
+    }
+}
+
+impl Add for Identifier {
+    type Output = Identifier;
+
+    fn add(self, other: Identifier) -> Identifier {
+        let mut limbs = [0; LIMBS];
+        let mut carry = false;
+        for (index, limb) in limbs.iter_mut().enumerate() {
+            let (sum, first_overflow) = self.limbs[index].overflowing_add(other.limbs[index]);
+            let (sum, second_overflow) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = first_overflow || second_overflow;
+        }
+        Identifier { limbs }.masked()
+    }
+}
+
+impl Sub for Identifier {
+    type Output = Identifier;
+
+    // Clockwise distance from other to self
+    fn sub(self, other: Identifier) -> Identifier {
+        let mut limbs = [0; LIMBS];
+        let mut borrow = false;
+        for (index, limb) in limbs.iter_mut().enumerate() {
+            let (difference, first_overflow) =
+                self.limbs[index].overflowing_sub(other.limbs[index]);
+            let (difference, second_overflow) = difference.overflowing_sub(borrow as u64);
+            *limb = difference;
+            borrow = first_overflow || second_overflow;
+        }
+        Identifier { limbs }.masked()
+    }
+}
+
+impl Ord for Identifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs.iter().rev().cmp(other.limbs.iter().rev())
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Written in decimal, like the u16 positions used to be
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        let mut digits = vec![];
+        let mut remaining = *self;
+        while !remaining.is_zero() {
+            digits.push(b'0' + remaining.divide_by(10) as u8);
+        }
+        digits.reverse();
+
+        write!(
+            f,
+            "{}",
+            String::from_utf8(digits).expect("Digits are ASCII")
+        )
+    }
+}
+
+impl fmt::Debug for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for Identifier {
+    type Err = String;
+
+    // Parses a decimal number, which has to fit on the ring
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.is_empty() {
+            return Err(String::from("Identifier cannot be empty"));
+        }
+
+        let mut parsed = Identifier::zero();
+        for character in value.chars() {
+            let digit = character
+                .to_digit(10)
+                .ok_or(format!("Identifier {} is not a decimal number", value))?;
+
+            // Multiply by ten and add the digit without masking, then check it still fits
+            let mut multiplied = [0u64; LIMBS];
+            let mut carry: u128 = digit as u128;
+            for (index, limb) in multiplied.iter_mut().enumerate() {
+                let value = parsed.limbs[index] as u128 * 10 + carry;
+                *limb = value as u64;
+                carry = value >> 64;
+            }
+            parsed = Identifier { limbs: multiplied };
+
+            if carry != 0 || parsed.masked() != parsed {
+                return Err(format!(
+                    "Identifier {} does not fit on a ring of {} bits",
+                    value,
+                    ring_bits()
+                ));
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+impl<'a> FromParam<'a> for Identifier {
+    type Error = String;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        param.parse()
+    }
+}
+
+// Serialized as a decimal string, as JSON numbers cannot hold 160 bits
+impl Serialize for Identifier {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct IdentifierVisitor;
+
+impl<'de> Visitor<'de> for IdentifierVisitor {
+    type Value = Identifier;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a position on the ring, as a decimal string or number")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Identifier, E> {
+        value.parse().map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Identifier, E> {
+        value.to_string().parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Identifier {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(IdentifierVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_160: &str = "1461501637330902918203684832716283019655932542975"; // 2^160 - 1
+
+    // The width is process-wide, so every test runs on the full 160 bit ring
+    fn full_ring() {
+        let _ = set_ring_bits(MAX_RING_BITS);
+        assert_eq!(ring_bits(), MAX_RING_BITS);
+    }
+
+    fn id(value: &str) -> Identifier {
+        value.parse().expect("Valid identifier")
+    }
+
+    #[test]
+    fn addition_and_subtraction_wrap_around() {
+        full_ring();
+        let one = Identifier::from_u64(1);
+
+        assert_eq!(Identifier::max() + one, Identifier::zero());
+        assert_eq!(Identifier::zero() - one, Identifier::max());
+        assert_eq!(
+            Identifier::from_u64(5) - Identifier::from_u64(7),
+            Identifier::max() - one
+        );
+        // Carries and borrows cross the limbs
+        assert_eq!(
+            Identifier::from_u64(u64::MAX) + one,
+            Identifier::power_of_two(64)
+        );
+        assert_eq!(
+            Identifier::power_of_two(128) - one,
+            id("340282366920938463463374607431768211455")
+        );
+    }
+
+    #[test]
+    fn ring_fraction_rounds_down() {
+        full_ring();
+        assert_eq!(
+            Identifier::ring_fraction(3),
+            id("487167212443634306067894944238761006551977514325")
+        );
+        assert_eq!(
+            Identifier::ring_fraction(7),
+            id("208785948190128988314812118959469002807990363282")
+        );
+        assert_eq!(Identifier::ring_fraction(2), Identifier::power_of_two(159));
+    }
+
+    #[test]
+    fn power_of_two_wraps_past_the_ring() {
+        full_ring();
+        assert_eq!(
+            Identifier::power_of_two(159).to_string(),
+            "730750818665451459101842416358141509827966271488"
+        );
+        assert_eq!(Identifier::power_of_two(160), Identifier::zero());
+        assert_eq!(Identifier::power_of_two(0), Identifier::from_u64(1));
+        assert_eq!(Identifier::power_of_two(159).as_ring_share(), 0.5);
+    }
+
+    #[test]
+    fn longest_range_is_not_the_whole_ring() {
+        full_ring();
+        let one = Identifier::from_u64(1);
+        let position = Identifier::from_u64(10);
+
+        // A successor right before us leaves out its own position
+        assert!((position - one).is_in_range(position - one, one));
+        assert!(!(position - one).is_in_range(position, Identifier::max()));
+        assert!((position - one - one).is_in_range(position, Identifier::max()));
+        assert!(position.is_in_range(position, Identifier::max()));
+
+        // Being our own successor covers every position
+        assert_eq!(position - position, Identifier::whole_ring());
+        assert!((position - one).is_in_range(position, Identifier::whole_ring()));
+        assert!(position.is_in_range(position, Identifier::whole_ring()));
+        assert_eq!(Identifier::whole_ring().range_length(), Identifier::max());
+        assert_eq!(one.range_length(), one);
+    }
+
+    #[test]
+    fn decimal_round_trip() {
+        full_ring();
+        assert_eq!(Identifier::zero().to_string(), "0");
+        assert_eq!(Identifier::max().to_string(), MAX_160);
+        assert_eq!(id(MAX_160), Identifier::max());
+        assert_eq!(id("000123"), Identifier::from_u64(123));
+    }
+
+    #[test]
+    fn parse_rejects_what_does_not_fit() {
+        full_ring();
+        assert!("1461501637330902918203684832716283019655932542976"
+            .parse::<Identifier>()
+            .is_err());
+        assert!(
+            "99999999999999999999999999999999999999999999999999999999999"
+                .parse::<Identifier>()
+                .is_err()
+        );
+        assert!("".parse::<Identifier>().is_err());
+        assert!("12a".parse::<Identifier>().is_err());
+        assert!("-1".parse::<Identifier>().is_err());
+    }
+
+    #[test]
+    fn digest_is_read_big_endian() {
+        full_ring();
+        let digest: Vec<u8> = (1..=20).collect();
+        assert_eq!(
+            Identifier::from_digest(&digest),
+            id("5753854965885600108575829560559299546819203860")
+        );
+        // Anything past the 160 bits of a SHA-1 digest is ignored
+        let mut longer = digest.clone();
+        longer.push(0xff);
+        assert_eq!(
+            Identifier::from_digest(&longer),
+            Identifier::from_digest(&digest)
+        );
+    }
+}
@@ -0,0 +1,58 @@
+#!/bin/bash
+
+# Local certificate authority for test networks. Creates the CA on first use, then issues a certificate
+# and key for every name given, signed by it. Hostnames and IP addresses go in the certificate, so nodes
+# can check them when connecting. The name "client" gets a certificate for curl and other clients.
+
+regex_ip_address="^[0-9]+\.[0-9]+\.[0-9]+\.[0-9]+$"
+DAYS=365
+
+
+if [ -z "$2" ]; then
+    echo "Please provide a directory and at least one node hostname: $0 <directory> <hostname> [hostname...]"
+    exit 1
+fi
+
+if ! command -v openssl > /dev/null; then
+    echo "openssl is required to create certificates."
+    exit 1
+fi
+
+directory=$1
+shift
+mkdir -p "$directory"
+
+
+# The CA is kept between runs, so certificates issued later are trusted by the nodes already running
+if [ -f "$directory/ca.pem" ] && [ -f "$directory/ca-key.pem" ]; then
+    echo "Using existing CA in $directory."
+else
+    echo "Creating CA in $directory..."
+    openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+        -keyout "$directory/ca-key.pem" -out "$directory/ca.pem" -days $DAYS \
+        -subj "/CN=INF3200 local CA" \
+        -addext "basicConstraints=critical,CA:TRUE" \
+        -addext "keyUsage=critical,keyCertSign,cRLSign" 2> /dev/null || exit 1
+fi
+
+
+for name in "$@"; do
+    if [[ "$name" =~ $regex_ip_address ]]; then
+        subject_alt_name="IP:$name"
+    else
+        subject_alt_name="DNS:$name"
+    fi
+
+    # Nodes act as both server and client, so every certificate is valid for both
+    openssl req -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+        -keyout "$directory/$name-key.pem" -out "$directory/$name.csr" \
+        -subj "/CN=$name" 2> /dev/null || exit 1
+    openssl x509 -req -in "$directory/$name.csr" -days $DAYS \
+        -CA "$directory/ca.pem" -CAkey "$directory/ca-key.pem" -CAcreateserial \
+        -out "$directory/$name.pem" \
+        -extfile <(printf "subjectAltName=%s\nextendedKeyUsage=serverAuth,clientAuth\nbasicConstraints=CA:FALSE\n" "$subject_alt_name") \
+        2> /dev/null || exit 1
+    rm "$directory/$name.csr"
+
+    echo "Issued $directory/$name.pem and $directory/$name-key.pem"
+done
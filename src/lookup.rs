@@ -0,0 +1,102 @@
+use rocket::serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Instant;
+
+use crate::failure_detector::node_id;
+use crate::identifier::Identifier;
+use crate::stabilization::is_same_node;
+use crate::{http_connect, Node};
+
+// How a node finds the owner of a key it does not hold itself
+#[derive(FromFormField, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum LookupMode {
+    // The request is handed to the closest node we know of, which passes it on in turn
+    #[default]
+    Recursive,
+    // We ask each node on the way for its closest node to the key, and only contact the owner with the request
+    Iterative,
+}
+
+impl FromStr for LookupMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "recursive" => Ok(LookupMode::Recursive),
+            "iterative" => Ok(LookupMode::Iterative),
+            other => Err(format!("Unknown lookup mode: {}", other)),
+        }
+    }
+}
+
+impl LookupMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LookupMode::Recursive => "recursive",
+            LookupMode::Iterative => "iterative",
+        }
+    }
+}
+
+// Answer of a node asked where to go next for a location
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct NextHop {
+    pub owner: bool, // True when `node` is the answering node itself, holding the location
+    pub node: Node,
+}
+
+// Walks towards the owner of the location through ring/next_hop, starting at `first_hop`.
+// Returns every node asked along with how long it took to answer, the owner being the last one.
+pub async fn find_owner_iteratively(
+    first_hop: Node,
+    location: Identifier,
+    max_hops: u16,
+) -> Result<Vec<(Node, f64)>, String> {
+    let mut path: Vec<(Node, f64)> = vec![];
+    let mut next = first_hop;
+
+    loop {
+        if path.len() >= max_hops as usize {
+            return Err(format!(
+                "Owner of location {} not found within {} hops",
+                location, max_hops
+            ));
+        }
+
+        let started = Instant::now();
+        let hop = match http_connect::get_from_node(
+            &next.hostname,
+            next.port,
+            &format!("ring/next_hop/{}", location),
+        )
+        .await
+        {
+            Err(_err) => return Err(format!("Could not ask {} for next hop", node_id(&next))),
+            Ok(response) => match response.json::<NextHop>() {
+                Err(_err) => return Err(String::from("Unable to parse next hop from JSON.")),
+                Ok(hop) => hop,
+            },
+        };
+        path.push((next, started.elapsed().as_secs_f64() * 1000.0));
+
+        if hop.owner {
+            return Ok(path);
+        }
+
+        // Being sent back to a node we already asked means the fingers are pointing in circles
+        if path
+            .iter()
+            .any(|(visited, _ms)| is_same_node(visited, &hop.node))
+        {
+            return Err(format!(
+                "Routing loop for location {} at {}",
+                location,
+                node_id(&hop.node)
+            ));
+        }
+
+        next = hop.node;
+    }
+}
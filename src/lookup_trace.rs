@@ -0,0 +1,191 @@
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+use crate::failure_detector::node_id;
+use crate::http_connect::Response;
+use crate::identifier::Identifier;
+use crate::membership::SENDER_HEADER;
+use crate::Node;
+
+pub const TRACE_HEADER: &str = "X-Trace"; // Set to 1 on a storage request to have its path traced
+pub const TRACE_PATH_HEADER: &str = "X-Trace-Path";
+pub const TRACE_HOPS_HEADER: &str = "X-Trace-Hops";
+pub const LOOKUP_HOPS_HEADER: &str = "X-Lookup-Hops"; // Nodes a storage request went through after the answering node
+pub const DEFAULT_LOOKUP_TTL: u16 = 64; // Hops before a lookup is considered stuck in a routing loop
+
+// One node visited by a lookup
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct LookupHop {
+    pub node: Node,
+    pub elapsed_ms: f64, // Time from this node receiving the lookup until it answered
+    pub hop_latency_ms: f64, // Time spent at this node and on the way to the next one
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct LookupTrace {
+    pub key: String,
+    pub location: Identifier,
+    pub hop_count: usize,
+    pub owner_found: bool, // False when the lookup ran out of hops before reaching the owner
+    pub hops: Vec<LookupHop>,
+}
+
+impl LookupTrace {
+    // Trace ending at the local node
+    pub fn ending_at(
+        key: &str,
+        location: Identifier,
+        local: &Node,
+        started: Instant,
+        owner_found: bool,
+    ) -> Self {
+        let elapsed_ms = elapsed_ms(started);
+        LookupTrace {
+            key: String::from(key),
+            location,
+            hop_count: 1,
+            owner_found,
+            hops: vec![LookupHop {
+                node: local.clone(),
+                elapsed_ms,
+                hop_latency_ms: elapsed_ms,
+            }],
+        }
+    }
+
+    // Trace of an iterative lookup driven by the local node. Each node asked is timed from our side,
+    // so its latency includes the round trip to it.
+    pub fn iterative(
+        key: &str,
+        location: Identifier,
+        local: &Node,
+        started: Instant,
+        path: Vec<(Node, f64)>,
+    ) -> Self {
+        let elapsed_ms = elapsed_ms(started);
+        let asked_ms: f64 = path.iter().map(|(_node, ms)| ms).sum();
+
+        let mut hops = vec![LookupHop {
+            node: local.clone(),
+            elapsed_ms,
+            hop_latency_ms: elapsed_ms - asked_ms,
+        }];
+        hops.extend(path.into_iter().map(|(node, ms)| LookupHop {
+            node,
+            elapsed_ms: ms,
+            hop_latency_ms: ms,
+        }));
+
+        LookupTrace {
+            key: String::from(key),
+            location,
+            hop_count: hops.len(),
+            owner_found: true,
+            hops,
+        }
+    }
+
+    // Puts the local node in front of the trace returned by the node we forwarded to
+    pub fn prepend(&mut self, local: &Node, started: Instant) {
+        let elapsed_ms = elapsed_ms(started);
+        let downstream_ms = self.hops.first().map_or(0.0, |hop| hop.elapsed_ms);
+        self.hops.insert(
+            0,
+            LookupHop {
+                node: local.clone(),
+                elapsed_ms,
+                hop_latency_ms: elapsed_ms - downstream_ms,
+            },
+        );
+        self.hop_count = self.hops.len();
+    }
+}
+
+// Whether the storage request asked for its path to be traced
+pub struct TraceRequest(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TraceRequest {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let enabled = request
+            .headers()
+            .get_one(TRACE_HEADER)
+            .is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+        Outcome::Success(TraceRequest(enabled))
+    }
+}
+
+// Whether the request came straight from a client, rather than being forwarded by another node. Only
+// the node a lookup started at records its hop count, so every lookup is counted once.
+pub struct ClientRequest(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientRequest {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(ClientRequest::of(request))
+    }
+}
+
+impl ClientRequest {
+    // Other nodes name themselves on every request they send
+    pub fn of(request: &Request<'_>) -> Self {
+        return ClientRequest(request.headers().get_one(SENDER_HEADER).is_none());
+    }
+}
+
+// Hops reported by the node we forwarded a storage request to, 0 if it did not say
+pub fn downstream_hops(response: &Response) -> usize {
+    return response
+        .headers
+        .get(&LOOKUP_HOPS_HEADER.to_lowercase())
+        .and_then(|hops| hops.parse().ok())
+        .unwrap_or(0);
+}
+
+impl TraceRequest {
+    // Headers to pass on when forwarding, so the next node traces as well
+    pub fn forward_headers(&self) -> Vec<(&'static str, &'static str)> {
+        match self.0 {
+            true => vec![(TRACE_HEADER, "1")],
+            false => vec![],
+        }
+    }
+
+    // Path of a request answered by the local node
+    pub fn local_path(&self, local: &Node, started: Instant) -> Option<Vec<String>> {
+        match self.0 {
+            true => Some(vec![trace_hop(local, started)]),
+            false => None,
+        }
+    }
+
+    // Path of a forwarded request, the local node followed by the path reported downstream
+    pub fn forwarded_path(
+        &self,
+        local: &Node,
+        started: Instant,
+        response: &Response,
+    ) -> Option<Vec<String>> {
+        let mut path = self.local_path(local, started)?;
+        if let Some(downstream) = response.headers.get(&TRACE_PATH_HEADER.to_lowercase()) {
+            path.extend(downstream.split(", ").map(String::from));
+        }
+        Some(path)
+    }
+}
+
+// A hop as listed in the trace header, with the time from this node receiving the request until it answered
+fn trace_hop(node: &Node, started: Instant) -> String {
+    format!("{} {:.2}ms", node_id(node), elapsed_ms(started))
+}
+
+fn elapsed_ms(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64() * 1000.0
+}
@@ -1,20 +1,24 @@
 #[macro_use]
 extern crate rocket;
 
-use minreq::Response;
-use rocket::http::Status;
+use http_connect::Response;
+use rocket::http::{ContentType, Header, Status};
 use rocket::response::status::{self, BadRequest, Conflict, Created, Custom, NoContent};
 use rocket::serde::Deserialize;
 use rocket::serde::{json::Json, Serialize};
 use rocket::{Shutdown, State};
 use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt::format;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 // Declare and import the storage module
 mod storage;
-use storage::Storage;
+use storage::{EngineKind, EngineSettings, Entry};
 
 // Declare and import the nodeConfig module
 mod node_config;
@@ -22,7 +26,49 @@ use node_config::NodeConfig;
 
 mod http_connect;
 
-const RING_SIZE: u16 = u16::MAX; // Maximum size of the ring, and thereby maximum number of nodes supported
+mod connection_pool;
+use connection_pool::PoolStats;
+
+mod rpc;
+
+mod tls;
+
+mod auth;
+use auth::Authorized;
+
+mod membership;
+use membership::{RejectedCalls, RingMember, SameNetwork};
+
+mod metrics;
+use metrics::{NodeMetrics, StorageOperation};
+
+mod stabilization;
+use stabilization::StabilizationSettings;
+
+mod failure_detector;
+use failure_detector::{FailureDetectorSettings, NeighbourHealth, NeighbourStatus};
+
+mod identifier;
+use identifier::Identifier;
+
+mod virtual_nodes;
+
+mod lookup;
+use lookup::{LookupMode, NextHop};
+
+mod lookup_trace;
+use lookup_trace::{ClientRequest, LookupTrace, TraceRequest, DEFAULT_LOOKUP_TTL};
+
+mod rebalance;
+use rebalance::{NodeLoad, RebalanceReport};
+
+const DEFAULT_STABILIZE_INTERVAL_MS: u64 = 5000;
+const DEFAULT_FIX_FINGERS_INTERVAL_MS: u64 = 30000;
+const DEFAULT_TOMBSTONE_TTL_MS: u64 = 600000;
+const DEFAULT_HEARTBEAT_INTERVAL_MS: u64 = 1000;
+const DEFAULT_SUSPECT_AFTER: u32 = 2;
+const DEFAULT_DEAD_AFTER: u32 = 5;
+const PING_TIMEOUT_MS: u64 = 500;
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(crate = "rocket::serde")]
@@ -38,14 +84,23 @@ struct NodeInfo {
 struct Node {
     hostname: String,
     port: u16,
-    position: u16,
-    range: u16,
+    process: u16, // Port of the first virtual node of the process hosting this node
+    position: Identifier,
+    range: Identifier,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(crate = "rocket::serde")]
 struct Network {
     network_id: String,
+    #[serde(default = "default_replication_factor")]
+    replication_factor: usize, // Number of nodes each key is stored on, the owner and its next successors
+    #[serde(default = "identifier::ring_bits")]
+    ring_bits: u32, // Width of positions on the ring, the same on every node
+}
+
+fn default_replication_factor() -> usize {
+    1
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -55,10 +110,19 @@ struct JoinNetworkInformation {
     longest_range: LongestRangeResponse,
 }
 
+// Only the network part of JoinNetworkInformation, which can be read whatever the size of the ring is
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct JoinNetworkDescription {
+    network: Network,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(crate = "rocket::serde")]
 struct SuppliedNetworkInformation {
     network_id: String,
+    #[serde(default = "default_replication_factor")]
+    replication_factor: usize,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -68,6 +132,27 @@ struct SuppliedNode {
     port: u16,
 }
 
+// Body of network/join, the node to join through and how to pick our position
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+struct JoinRequest {
+    #[serde(flatten)]
+    existing_node: SuppliedNode,
+    #[serde(default)]
+    strategy: JoinStrategy,
+}
+
+// How a joining node picks its position on the ring
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+enum JoinStrategy {
+    // Halve the longest range in the network, found by walking the whole ring. O(N) messages per join.
+    #[default]
+    SplitLongestRange,
+    // Classic Chord, the position is the hash of hostname:port and its current holder is found through a lookup
+    Hash,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(crate = "rocket::serde")]
 struct LongestRangeRequest {
@@ -80,43 +165,600 @@ struct LongestRangeResponse {
     holder: Node,
 }
 
+// Interval of the ring [position, position + range), used when handing keys over to a new owner
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+struct KeyRange {
+    position: Identifier,
+    range: Identifier,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+struct KeyValuePair {
+    key: String,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+// Consistency level of a storage request, deciding how many of the replication_factor replicas must answer
+#[derive(FromFormField, Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+enum Consistency {
+    One,
+    Quorum,
+    All,
+}
+
+impl Consistency {
+    fn required_replicas(&self, replication_factor: usize) -> usize {
+        match self {
+            Consistency::One => 1,
+            Consistency::Quorum => replication_factor / 2 + 1,
+            Consistency::All => replication_factor,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Consistency::One => "one",
+            Consistency::Quorum => "quorum",
+            Consistency::All => "all",
+        }
+    }
+}
+
+impl FromStr for Consistency {
+    type Err = String;
+
+    fn from_str(consistency: &str) -> Result<Self, Self::Err> {
+        match consistency {
+            "one" => Ok(Consistency::One),
+            "quorum" => Ok(Consistency::Quorum),
+            "all" => Ok(Consistency::All),
+            other => Err(format!("Unknown consistency level: {}", other)),
+        }
+    }
+}
+
+const REPLICAS_ANSWERED_HEADER: &str = "X-Replicas-Answered";
+
+// Value of a storage request, along with how many replicas answered or acknowledged it
+struct ReplicatedResponse {
+    value: String,
+    replicas_answered: Header<'static>,
+    trace: Option<Vec<String>>, // Nodes visited, starting with us, when the request asked for a trace
+    lookup_hops: usize,         // Nodes the request went through after us, 0 when we answered it
+}
+
+impl<'r> rocket::response::Responder<'r, 'static> for ReplicatedResponse {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = self.value.respond_to(request)?;
+        response.set_header(self.replicas_answered);
+        response.set_header(Header::new(
+            lookup_trace::LOOKUP_HOPS_HEADER,
+            self.lookup_hops.to_string(),
+        ));
+
+        // Counted once the answer is on its way back to the client, at the node the request started at
+        if let Some(node_config) = request.rocket().state::<Arc<RwLock<NodeConfig>>>() {
+            if self.lookup_hops > 0 {
+                record_lookup_hops(node_config, &ClientRequest::of(request), self.lookup_hops);
+            }
+        }
+
+        if let Some(trace) = self.trace {
+            response.set_header(Header::new(
+                lookup_trace::TRACE_HOPS_HEADER,
+                trace.len().to_string(),
+            ));
+            response.set_header(Header::new(
+                lookup_trace::TRACE_PATH_HEADER,
+                trace.join(", "),
+            ));
+        }
+
+        return Ok(response);
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(crate = "rocket::serde")]
 struct FingerTableInformation {
     size: u16,
+    #[serde(default)]
+    mode: FingerTableMode,
+}
+
+// How the entries of a finger table are chosen
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+enum FingerTableMode {
+    // `size` nodes evenly spaced in the list of all nodes, found by walking the whole ring
+    #[default]
+    EvenlySpaced,
+    // Textbook Chord, entry i is the node responsible for position + 2^i, found through lookups
+    Chord,
 }
 
-fn key_to_location(key: &str) -> u16 {
+fn key_to_location(key: &str) -> Identifier {
     // We use the hasher to hash the given key
     let mut hasher = Sha1::new();
     hasher.update(key.as_bytes());
     let hashed = hasher.finalize();
 
-    // For our RING_SIZE = 2^16 = 65 536 that means reading the first two bytes of the hash and interpreting them as a u16.
-    let hash_slice: [u8; 2] = [hashed[0], hashed[1]];
-    let hashed_location: u16 = u16::from_be_bytes(hash_slice);
+    // For a ring of 2^bits positions that means reading the first bits of the hash as a big-endian number.
+    // With the default of 16 bits these are the first two bytes.
+    return Identifier::from_digest(&hashed);
+}
 
-    return hashed_location;
+fn is_location_in_range(location: Identifier, position: Identifier, range: Identifier) -> bool {
+    return location.is_in_range(position, range);
 }
 
-fn is_location_in_range(location: u16, position: u16, range: u16) -> bool {
-    // Special case for range wrapping circle
-    if RING_SIZE - position < range {
-        return location >= position || location < range - (RING_SIZE - position);
+// Distance between the positions going the shortest way around the ring, and whether that way is clockwise
+fn shortest_distance_on_circumference(p1: Identifier, p2: Identifier) -> (Identifier, bool) {
+    let forwards_distance = p2 - p1;
+    let backwards_distance = p1 - p2;
+
+    if forwards_distance < backwards_distance {
+        return (forwards_distance, true);
     } else {
-        return location >= position && location < position + range;
+        return (backwards_distance, false);
     }
 }
 
-fn shortest_distance_on_circumference(p1: u16, p2: u16) -> i32 {
-    let forwards_distance = i32::from(p2) - i32::from(p1);
-    let backwards_distance = (i32::from(RING_SIZE) - i32::from(p2)) + i32::from(p1);
+// Whether the location lies strictly between start and end, going clockwise around the ring
+fn is_location_between(location: Identifier, start: Identifier, end: Identifier) -> bool {
+    if start < end {
+        return location > start && location < end;
+    } else {
+        return location > start || location < end;
+    }
+}
 
-    if forwards_distance < backwards_distance {
-        return forwards_distance.into();
+// Sets a new successor, our range then extends up to the position of the successor
+fn set_successor(config: &mut NodeConfig, successor: Node) {
+    // Being our own successor gives the whole ring, as there is nobody else to share it with
+    config.local.range = successor.position - config.local.position;
+    config.successor = Some(successor);
+}
+
+// Picks the known node closest to the location, being either our precessor or successor
+// depending on direction, or any node in the finger table that is closer still
+fn closest_node_to_location(config: &NodeConfig, location: Identifier) -> &Node {
+    let (mut forward_node_distance, clockwise) =
+        shortest_distance_on_circumference(config.local.position, location);
+
+    let mut forward_node = if !clockwise {
+        config
+            .precessor
+            .as_ref()
+            .expect("Could not forward, node has no precessor")
     } else {
-        return -i32::from(backwards_distance);
+        config
+            .successor
+            .as_ref()
+            .expect("Could not forward, node has no successor")
+    };
+
+    // See if the key is closer to any node in the finger table
+    for node in config.finger_table.iter() {
+        let (distance, _clockwise) = shortest_distance_on_circumference(node.position, location);
+        if distance < forward_node_distance {
+            forward_node = node;
+            forward_node_distance = distance;
+        }
+    }
+
+    return forward_node;
+}
+
+// Walks the ring through ring/successor until we know the next `wanted` nodes after the local node,
+// stopping early if the ring is smaller than that
+async fn build_successor_list(
+    local: &Node,
+    successor: Node,
+    wanted: usize,
+) -> Result<Vec<Node>, String> {
+    let mut successor_list: Vec<Node> = vec![];
+    let mut current_node = successor;
+
+    while successor_list.len() < wanted
+        && (current_node.hostname != local.hostname || current_node.port != local.port)
+    {
+        // Our own virtual nodes, and further ones of a process already in the list, would go down
+        // together with a node holding the same keys, so they are passed over
+        if !virtual_nodes::is_same_process(&current_node, local)
+            && !successor_list
+                .iter()
+                .any(|node| virtual_nodes::is_same_process(node, &current_node))
+        {
+            successor_list.push(current_node.clone());
+
+            if successor_list.len() == wanted {
+                break;
+            }
+        }
+
+        current_node = match http_connect::get_from_node(
+            &current_node.hostname,
+            current_node.port,
+            "ring/successor",
+        )
+        .await
+        {
+            Err(_err) => return Err(String::from("Could not connect to node to get successor.")),
+            Ok(response) => match response.json::<Node>() {
+                Err(_err) => {
+                    return Err(String::from(
+                        "Unable to parse received successor from JSON.",
+                    ))
+                }
+                Ok(parsed) => parsed,
+            },
+        };
+    }
+
+    return Ok(successor_list);
+}
+
+// Reads the key from the given nodes until `wanted` of them have answered, a node answering
+// that it does not have the key counts as an answer. Returns the number of answers and the newest entry.
+async fn read_from_replicas(replicas: &[Node], key: &str, wanted: usize) -> (usize, Option<Entry>) {
+    let mut answered = 0;
+    let mut newest: Option<Entry> = None;
+
+    for replica in replicas.iter() {
+        if answered >= wanted {
+            break;
+        }
+
+        match http_connect::get_from_node(
+            &replica.hostname,
+            replica.port,
+            &format!("storage/{}/replica", key),
+        )
+        .await
+        {
+            Ok(response) => match response.json::<Entry>() {
+                Ok(entry) => {
+                    answered += 1;
+                    if newest
+                        .as_ref()
+                        .is_none_or(|newest| entry.supersedes(newest))
+                    {
+                        newest = Some(entry);
+                    }
+                }
+                Err(_err) => println!(
+                    "Unable to parse replica from {}:{}",
+                    replica.hostname, replica.port
+                ),
+            },
+            Err(node_connection_error) => {
+                if node_connection_error.status_code() == Some(404) {
+                    answered += 1;
+                }
+            }
+        }
+    }
+
+    return (answered, newest);
+}
+
+// Finds the nodes after the owner, which hold the replicas of its keys, through ring/find_successor. The
+// owner itself may not answer, so each step asks any other node we know until one does.
+async fn find_replicas_of(
+    node_config: &RwLock<NodeConfig>,
+    owner: &Node,
+    wanted: usize,
+) -> Vec<Node> {
+    let (local, known) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+        // The same node can show up in several of the lists, it is asked only once
+        let mut seen: HashSet<String> = HashSet::new();
+        let known: Vec<Node> = config
+            .successor_list
+            .iter()
+            .chain(config.finger_table.iter())
+            .chain(config.successor.iter())
+            .chain(config.precessor.iter())
+            .filter(|node| {
+                !stabilization::is_same_node(node, owner)
+                    && !stabilization::is_same_node(node, &config.local)
+                    && seen.insert(failure_detector::node_id(node))
+            })
+            .cloned()
+            .collect();
+        (config.local.clone(), known)
+    };
+
+    let mut replicas: Vec<Node> = vec![];
+    let mut previous = owner.clone();
+
+    while replicas.len() < wanted {
+        // The next node sits where the range of the previous one ends. The range we have of the owner
+        // may be older than a node we know that has joined inside it since.
+        let location = known
+            .iter()
+            .chain(std::iter::once(&local))
+            .map(|node| node.position - previous.position)
+            .filter(|distance| {
+                *distance > Identifier::zero() && *distance < previous.range.range_length()
+            })
+            .min()
+            .map_or(previous.position + previous.range, |distance| {
+                previous.position + distance
+            });
+
+        let mut next: Option<Node> = None;
+        if is_location_in_range(location, local.position, local.range) {
+            next = Some(local.clone());
+        }
+        for node in known.iter() {
+            if next.is_some() {
+                break;
+            }
+            if let Ok(response) = http_connect::get_from_node(
+                &node.hostname,
+                node.port,
+                &format!("ring/find_successor/{}", location),
+            )
+            .await
+            {
+                next = response.json::<Node>().ok();
+            }
+        }
+
+        match next {
+            None => break,
+            // Back at the owner, or at a node we already have, the ring is smaller than the replica set
+            Some(next)
+                if stabilization::is_same_node(&next, owner)
+                    || replicas
+                        .iter()
+                        .any(|replica| stabilization::is_same_node(replica, &next)) =>
+            {
+                break
+            }
+            // Virtual nodes of a process already holding the key go down together with it
+            Some(next)
+                if virtual_nodes::is_same_process(&next, owner)
+                    || replicas
+                        .iter()
+                        .any(|replica| virtual_nodes::is_same_process(replica, &next)) =>
+            {
+                previous = next;
+            }
+            Some(next) => {
+                replicas.push(next.clone());
+                previous = next;
+            }
+        }
+    }
+
+    return replicas;
+}
+
+// Sends the entry to every given node, returning how many acknowledged it
+async fn write_to_replicas(replicas: &[Node], key: &str, entry: &Entry) -> usize {
+    let mut acknowledged = 0;
+
+    for replica in replicas.iter() {
+        match http_connect::write_json_to_node(
+            http_connect::WriteOperations::Put,
+            &replica.hostname,
+            replica.port,
+            &format!("storage/{}/replica", key),
+            entry,
+        )
+        .await
+        {
+            Ok(_response) => acknowledged += 1,
+            Err(_err) => println!(
+                "Could not replicate key to {}:{}",
+                replica.hostname, replica.port
+            ),
+        }
+    }
+
+    return acknowledged;
+}
+
+fn storage_path(key: &str, consistency: Option<Consistency>, lookup: LookupMode) -> String {
+    match consistency {
+        Some(consistency) => format!(
+            "storage/{}?consistency={}&lookup={}",
+            key,
+            consistency.as_str(),
+            lookup.as_str()
+        ),
+        None => format!("storage/{}?lookup={}", key, lookup.as_str()),
+    }
+}
+
+// Lookup mode and first node on the way to the location, read under a lock that is released before forwarding
+fn forwarding_start(
+    node_config: &RwLock<NodeConfig>,
+    location: Identifier,
+    lookup: Option<LookupMode>,
+) -> (LookupMode, Node) {
+    let config = node_config.read().expect("RWLock is poisoned");
+    return (
+        lookup.unwrap_or(config.lookup_mode),
+        closest_node_to_location(&config, location).clone(),
+    );
+}
+
+// Node to send a request for the location to when we do not hold it ourselves, along with the number of
+// nodes asked before it. In iterative mode we find the owner ourselves and go straight to it, instead of
+// through a chain of nodes each waiting on the next.
+async fn forwarding_target(
+    first_hop: Node,
+    location: Identifier,
+    lookup: LookupMode,
+) -> Result<(Node, usize), Custom<String>> {
+    match lookup {
+        LookupMode::Recursive => return Ok((first_hop, 0)),
+        LookupMode::Iterative => {
+            match lookup::find_owner_iteratively(first_hop, location, DEFAULT_LOOKUP_TTL).await {
+                Ok(mut path) => {
+                    let (owner, _ms) = path.pop().expect("Iterative lookup returned no nodes");
+                    return Ok((owner, path.len()));
+                }
+                Err(error_message) => {
+                    println!("{}", &error_message);
+                    return Err(status::Custom(Status::FailedDependency, error_message));
+                }
+            }
+        }
+    }
+}
+
+// Counts the hops of a forwarded request or lookup at the node it started at
+fn record_lookup_hops(node_config: &RwLock<NodeConfig>, client: &ClientRequest, hops: usize) {
+    if client.0 {
+        node_config
+            .read()
+            .expect("RWLock is poisoned")
+            .metrics
+            .record_lookup_hops(hops);
+    }
+}
+
+// A forwarded storage request that timed out is not sent again. Every node along a recursive chain would
+// retry it on its own, so a single hung owner would be asked again and again while the client waits.
+fn forwarding_options<'a>(headers: &'a [(&'a str, &'a str)]) -> http_connect::CallOptions<'a> {
+    return http_connect::CallOptions {
+        headers,
+        request: http_connect::RequestSettings {
+            retry_timeouts: false,
+            ..http_connect::settings().request
+        },
+    };
+}
+
+// Passes on the number of replicas reported by the node we forwarded to
+fn replicas_answered_header(response: &Response) -> Header<'static> {
+    let replicas_answered = response
+        .headers
+        .get(&REPLICAS_ANSWERED_HEADER.to_lowercase())
+        .cloned()
+        .unwrap_or(String::from("1"));
+    Header::new(REPLICAS_ANSWERED_HEADER, replicas_answered)
+}
+
+fn is_local_node(config: &NodeConfig, node: &Node) -> bool {
+    node.hostname == config.local.hostname && node.port == config.local.port
+}
+
+// Whether the failure detector has declared our precessor dead
+fn is_precessor_dead(config: &NodeConfig, precessor: &Node) -> bool {
+    config
+        .neighbour_health
+        .get(&failure_detector::node_id(precessor))
+        .is_some_and(|health| health.status == NeighbourStatus::Dead)
+}
+
+// A ping is not retried, a node that misses it is simply asked again on the next heartbeat
+async fn is_node_alive(node: &Node) -> bool {
+    let ping_settings = http_connect::RequestSettings {
+        timeout: Duration::from_millis(PING_TIMEOUT_MS),
+        retries: 0,
+        ..http_connect::settings().request
+    };
+
+    http_connect::get_from_node_with_options(
+        &node.hostname,
+        node.port,
+        "helloworld",
+        http_connect::CallOptions {
+            request: ping_settings,
+            ..Default::default()
+        },
+    )
+    .await
+    .is_ok()
+}
+
+// Whether our precessor or successor still has us as its neighbour
+async fn neighbour_points_to_local(
+    local: &Node,
+    precessor: Option<Node>,
+    successor: Option<Node>,
+) -> bool {
+    let neighbours = [(precessor, "ring/successor"), (successor, "ring/precessor")];
+
+    for (neighbour, path) in neighbours {
+        let neighbour = match neighbour {
+            Some(neighbour) if !stabilization::is_same_node(local, &neighbour) => neighbour,
+            _ => continue,
+        };
+
+        if let Ok(response) =
+            http_connect::get_from_node(&neighbour.hostname, neighbour.port, path).await
+        {
+            if let Ok(node) = response.json::<Node>() {
+                return stabilization::is_same_node(local, &node);
+            }
+        }
     }
+
+    return false;
+}
+
+// Copies every key in our range from the replicas in our successor list, keeping whichever version is
+// newest. Used after recovering, as writes may have reached the replicas while we were unavailable.
+async fn fetch_owned_keys_from_replicas(node_config: &RwLock<NodeConfig>) -> usize {
+    let (owned_range, successor_list) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+        let owned_range = KeyRange {
+            position: config.local.position,
+            range: config.local.range,
+        };
+        (owned_range, config.successor_list.clone())
+    };
+    let mut fetched = 0;
+
+    for replica in successor_list.iter() {
+        let entries = match http_connect::write_json_to_node(
+            http_connect::WriteOperations::Post,
+            &replica.hostname,
+            replica.port,
+            "storage/handoff",
+            &owned_range,
+        )
+        .await
+        {
+            Ok(response) => match response.json::<Vec<KeyValuePair>>() {
+                Ok(entries) => entries,
+                Err(_err) => continue,
+            },
+            Err(_err) => continue,
+        };
+
+        let config = node_config.read().expect("RWLock is poisoned");
+        for pair in entries {
+            if let Ok(true) = config.storage.put_if_newer(&pair.key, pair.entry) {
+                fetched += 1;
+            }
+        }
+    }
+
+    return fetched;
+}
+
+fn entries_in_range(config: &NodeConfig, key_range: &KeyRange) -> Vec<KeyValuePair> {
+    config
+        .storage
+        .scan()
+        .into_iter()
+        .filter(|(key, _entry)| {
+            is_location_in_range(key_to_location(key), key_range.position, key_range.range)
+        })
+        .map(|(key, entry)| KeyValuePair { key, entry })
+        .collect()
 }
 
 // end-point to test if the server is running
@@ -139,13 +781,17 @@ fn helloworld(node_config: &State<Arc<RwLock<NodeConfig>>>) -> Result<String, Cu
 }
 
 #[get("/shutdown")]
-fn shutdown(shutdown: Shutdown) -> String {
+fn shutdown(_authorized: Authorized, _same_network: SameNetwork, shutdown: Shutdown) -> String {
     shutdown.notify();
     String::from("Bye!")
 }
 
 #[post("/sim-crash")]
-fn post_sim_crash(node_config: &State<Arc<RwLock<NodeConfig>>>) -> Result<(), Custom<String>> {
+fn post_sim_crash(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<(), Custom<String>> {
     let mut config = node_config.write().expect("RWLock is poisoned");
 
     if config.is_crashed() {
@@ -156,56 +802,233 @@ fn post_sim_crash(node_config: &State<Arc<RwLock<NodeConfig>>>) -> Result<(), Cu
     }
 
     config.crash();
+
+    // A crash takes down the whole process, and with it every virtual node
+    virtual_nodes::send_to_siblings(
+        &config.virtual_nodes,
+        http_connect::WriteOperations::Post,
+        "sim-crash",
+        String::new(),
+    );
     return Ok(());
 }
 
 #[post("/sim-recover")]
-fn post_sim_recover(node_config: &State<Arc<RwLock<NodeConfig>>>) -> () {
-    let mut config = node_config.write().expect("RWLock is poisoned");
-    config.recover();
-
-    // let join_node = None
-    // // Check is precessor is online
-    // if precessor online {
-    //     join_node = precessor
-    // }
-    // else if successor_online {
-    //     join_node = successor
-    // }
-    // else {
-    //     check finger table nodes
-    //         join_node = online node
-    // }
-
-    // If some
-    //     put_network_join(
-    //         node_config,
-    //         Json(SuppliedNode { hostname: .hostname, port: () }),
-    //     );
-    // Else
-    //     Cant join error
+async fn post_sim_recover(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<String, Custom<String>> {
+    let recovered = recover_node(node_config).await;
+    let virtual_nodes = node_config
+        .read()
+        .expect("RWLock is poisoned")
+        .virtual_nodes
+        .clone();
+
+    // Only once we are back in the ring, so the other virtual nodes can rejoin through us
+    virtual_nodes::send_to_siblings(
+        &virtual_nodes,
+        http_connect::WriteOperations::Post,
+        "sim-recover",
+        String::new(),
+    );
+
+    return recovered;
+}
+
+// Recovers from a simulated crash, and rejoins the ring through a former neighbour if we were connected
+async fn recover_node(node_config: &RwLock<NodeConfig>) -> Result<String, Custom<String>> {
+    let (local, precessor, successor, former_neighbours) = {
+        let mut config = node_config.write().expect("RWLock is poisoned");
+        config.recover();
+
+        if !config.connected {
+            return Ok(String::from("Node recovered, not connected to a network"));
+        }
+
+        // Former neighbours in order of preference: precessor, successor, then any other node we knew of
+        let mut former_neighbours: Vec<Node> = vec![];
+        former_neighbours.extend(config.precessor.clone());
+        former_neighbours.extend(config.successor.clone());
+        former_neighbours.extend(config.successor_list.clone());
+        former_neighbours.extend(config.finger_table.clone());
+        former_neighbours.retain(|node| !is_local_node(&config, node));
+
+        (
+            config.local.clone(),
+            config.precessor.clone(),
+            config.successor.clone(),
+            former_neighbours,
+        )
+    };
+
+    let mut contact: Option<Node> = None;
+    for node in former_neighbours {
+        if is_node_alive(&node).await {
+            contact = Some(node);
+            break;
+        }
+    }
+
+    let contact = match contact {
+        Some(contact) => contact,
+        None => {
+            let error_message = String::from(
+                "Node recovered, but no former neighbour is reachable to rejoin the ring through.",
+            );
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+    };
+    println!("Rejoining through {}:{}", contact.hostname, contact.port);
+
+    // While we were crashed the ring may have been repaired around us. If a neighbour still
+    // points to us we are part of the ring and only need to catch up, otherwise we join anew.
+    if neighbour_points_to_local(&local, precessor, successor.clone()).await {
+        let mut successor = successor.expect("Connected, but had no successor");
+        if let Ok(response) =
+            http_connect::get_from_node(&successor.hostname, successor.port, "ring/local").await
+        {
+            if let Ok(current) = response.json::<Node>() {
+                successor = current;
+            }
+        }
+
+        let wanted = {
+            let mut config = node_config.write().expect("RWLock is poisoned");
+            set_successor(&mut config, successor.clone());
+            config.replication_factor() - 1
+        };
+
+        match build_successor_list(&local, successor, wanted).await {
+            Ok(successor_list) => {
+                node_config
+                    .write()
+                    .expect("RWLock is poisoned")
+                    .successor_list = successor_list
+            }
+            Err(error_message) => println!("Could not rebuild successor list: {}", error_message),
+        };
+
+        let fetched = fetch_owned_keys_from_replicas(node_config).await;
+        return Ok(format!(
+            "Node recovered and caught up, fetched {} newer keys from replicas",
+            fetched
+        ));
+    }
+
+    // We were removed from the ring, so start over and join through the contact
+    let strategy = {
+        let mut config = node_config.write().expect("RWLock is poisoned");
+        config.connected = false;
+        config.network = None;
+        config.successor = None;
+        config.precessor = None;
+        config.successor_list.clear();
+        config.finger_table.clear();
+        config.join_strategy
+    };
+
+    let joined = join_network(
+        node_config,
+        &SuppliedNode {
+            hostname: contact.hostname,
+            port: contact.port,
+        },
+        strategy,
+    )
+    .await?;
+    let fetched = fetch_owned_keys_from_replicas(node_config).await;
+
+    return Ok(format!(
+        "Node recovered. {}, fetched {} newer keys from replicas",
+        joined, fetched
+    ));
 }
 
 // endpoint to retrive a value for a given
-#[get("/storage/<key>")]
-fn get_storage(
+#[get("/storage/<key>?<consistency>&<lookup>")]
+async fn get_storage(
+    _same_network: SameNetwork,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
     key: &str,
-) -> Result<String, Custom<String>> {
-    let config = node_config.read().expect("RWLock is poisoned");
+    consistency: Option<Consistency>,
+    lookup: Option<LookupMode>,
+    trace: TraceRequest,
+) -> Result<ReplicatedResponse, Custom<String>> {
+    let started = Instant::now();
+    let hashed_location: Identifier = key_to_location(key);
+
+    // Other nodes may take a while to answer, so they are not contacted while holding the lock
+    let (local, successor_list, replication_factor, required, is_owner) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
 
-    if config.is_crashed() {
-        return Err(status::Custom(
-            Status::ServiceUnavailable,
-            String::from("Node is crashed"),
-        ));
-    }
+        let replication_factor = config.replication_factor();
+        let required = consistency
+            .unwrap_or(Consistency::One)
+            .required_replicas(replication_factor);
+        let is_owner =
+            is_location_in_range(hashed_location, config.local.position, config.local.range);
+        config
+            .metrics
+            .record_storage_operation(StorageOperation::Get, !is_owner);
+        (
+            config.local.clone(),
+            config.successor_list.clone(),
+            replication_factor,
+            required,
+            is_owner,
+        )
+    };
 
-    let hashed_location: u16 = key_to_location(key);
+    if is_owner {
+        let local_entry = node_config
+            .read()
+            .expect("RWLock is poisoned")
+            .storage
+            .get(key);
+
+        // Our own copy is the first answer, the rest have to come from the successor list
+        let (answered, replica_entry) =
+            read_from_replicas(&successor_list, key, required - 1).await;
+        let answered = answered + 1;
 
-    if is_location_in_range(hashed_location, config.local.position, config.local.range) {
-        match config.storage.retrieve(key) {
-            Some(value) => return Ok(value),
+        if answered < required {
+            let error_message = format!("Only {} of {} replicas answered", answered, required);
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::ServiceUnavailable, error_message));
+        }
+
+        // Keep the highest version, repairing our own copy if a replica had a newer one
+        let newest = match (local_entry, replica_entry) {
+            (local, Some(replica))
+                if local.as_ref().is_none_or(|local| replica.supersedes(local)) =>
+            {
+                let config = node_config.read().expect("RWLock is poisoned");
+                let _ = config.storage.put_if_newer(key, replica.clone());
+                Some(replica)
+            }
+            (local, _) => local,
+        };
+
+        // A tombstone won, the key was deleted
+        match Entry::live(newest) {
+            Some(entry) => {
+                return Ok(ReplicatedResponse {
+                    value: entry.value,
+                    replicas_answered: Header::new(REPLICAS_ANSWERED_HEADER, answered.to_string()),
+                    trace: trace.local_path(&local, started),
+                    lookup_hops: 0,
+                })
+            }
             None => {
                 return Err(status::Custom(
                     Status::NotFound,
@@ -217,71 +1040,454 @@ fn get_storage(
 
     // Early returns for cases where key is under over jurisdiction, so if we get here we need to forward the request
     println!("Forwarding request!");
-    let mut forward_node_distance =
-        shortest_distance_on_circumference(config.local.position, hashed_location).abs();
+    let (lookup, first_hop) = forwarding_start(node_config, hashed_location, lookup);
+    let (forward_node, asked) = forwarding_target(first_hop, hashed_location, lookup).await?;
 
-    let mut forward_node =
-        if shortest_distance_on_circumference(config.local.position, hashed_location) < 0 {
-            config
-                .precessor
-                .as_ref()
-                .expect("Could not forward, node has no successor")
-        } else {
-            config
-                .successor
-                .as_ref()
-                .expect("Could not forward, node has no successor")
+    let forward_request_response = match http_connect::get_from_node_with_options(
+        &forward_node.hostname,
+        forward_node.port,
+        &storage_path(key, consistency, lookup),
+        forwarding_options(&trace.forward_headers()),
+    )
+    .await
+    {
+        Ok(response) => response,
+        Err(node_connection_error) => {
+            if node_connection_error.status_code() == Some(404) {
+                return Err(status::Custom(
+                    Status::NotFound,
+                    String::from("Key not found"),
+                ));
+            }
+
+            // The owner could not answer, so ask its replicas in its place. A node on the way to the
+            // owner that failed has either done so itself already, or is not the one to ask about.
+            let forwarded_to_owner =
+                is_location_in_range(hashed_location, forward_node.position, forward_node.range);
+            let replicas = match forwarded_to_owner {
+                true => find_replicas_of(node_config, &forward_node, replication_factor - 1).await,
+                false => vec![],
+            };
+            let (answered, replica_entry) = read_from_replicas(&replicas, key, required).await;
+            if forwarded_to_owner && answered >= required {
+                match Entry::live(replica_entry) {
+                    Some(entry) => {
+                        return Ok(ReplicatedResponse {
+                            value: entry.value,
+                            replicas_answered: Header::new(
+                                REPLICAS_ANSWERED_HEADER,
+                                answered.to_string(),
+                            ),
+                            trace: trace.local_path(&local, started),
+                            lookup_hops: 0,
+                        })
+                    }
+                    None => {
+                        return Err(status::Custom(
+                            Status::NotFound,
+                            String::from("Key not found"),
+                        ))
+                    }
+                }
+            }
+
+            let error_message = format!(
+                "Could not forward request to {}:{}, {}, and {} of {} replicas answered",
+                forward_node.hostname, forward_node.port, node_connection_error, answered, required
+            );
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+    };
+
+    return Ok(ReplicatedResponse {
+        replicas_answered: replicas_answered_header(&forward_request_response),
+        trace: trace.forwarded_path(&local, started, &forward_request_response),
+        value: String::from(forward_request_response.as_str().expect("No body found")),
+        lookup_hops: asked + lookup_trace::downstream_hops(&forward_request_response) + 1,
+    });
+}
+
+// endpoint to store a key-value pair
+#[put(
+    "/storage/<key>?<consistency>&<lookup>",
+    format = "text",
+    data = "<value>"
+)]
+async fn put_storage(
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    key: &str,
+    value: &str,
+    consistency: Option<Consistency>,
+    lookup: Option<LookupMode>,
+    trace: TraceRequest,
+) -> Result<ReplicatedResponse, Custom<String>> {
+    let started = Instant::now();
+    let hashed_location: Identifier = key_to_location(key);
+
+    // Other nodes may take a while to answer, so they are not contacted while holding the lock
+    let (local, successor_list, required, is_owner) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
+
+        let required = consistency
+            .unwrap_or(Consistency::One)
+            .required_replicas(config.replication_factor());
+        let is_owner =
+            is_location_in_range(hashed_location, config.local.position, config.local.range);
+        config
+            .metrics
+            .record_storage_operation(StorageOperation::Put, !is_owner);
+        (
+            config.local.clone(),
+            config.successor_list.clone(),
+            required,
+            is_owner,
+        )
+    };
+
+    if is_owner {
+        // As owner of the key we decide the order of writes to it
+        let stored = node_config
+            .read()
+            .expect("RWLock is poisoned")
+            .storage
+            .put_next_version(key, String::from(value));
+        let entry = match stored {
+            Ok(entry) => entry,
+            Err(err) => {
+                let error_message = format!("Could not persist value. Error: {}", err);
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::InternalServerError, error_message));
+            }
         };
 
-    // See if the key is closer to any node in the finger table
-    if config.finger_table.len() > 0 {
-        for node in config.finger_table.iter() {
-            if shortest_distance_on_circumference(node.position, hashed_location).abs()
-                < forward_node_distance
-            {
-                forward_node = node;
-                forward_node_distance =
-                    shortest_distance_on_circumference(node.position, hashed_location).abs();
+        // Copy the value to the next successors, so it survives us crashing
+        let acknowledged = write_to_replicas(&successor_list, key, &entry).await + 1;
+
+        if acknowledged < required {
+            let error_message = format!(
+                "Only {} of {} replicas acknowledged the write",
+                acknowledged, required
+            );
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::ServiceUnavailable, error_message));
+        }
+
+        return Ok(ReplicatedResponse {
+            value: String::from(value),
+            replicas_answered: Header::new(REPLICAS_ANSWERED_HEADER, acknowledged.to_string()),
+            trace: trace.local_path(&local, started),
+            lookup_hops: 0,
+        });
+    }
+
+    // Early returns for cases where key is under over jurisdiction, so if we get here we need to forward the request
+    println!("Forwarding request!");
+    let (lookup, first_hop) = forwarding_start(node_config, hashed_location, lookup);
+    let (forward_node, asked) = forwarding_target(first_hop, hashed_location, lookup).await?;
+
+    match http_connect::write_body_to_node_with_options(
+        http_connect::WriteOperations::Put,
+        &forward_node.hostname,
+        forward_node.port,
+        &storage_path(key, consistency, lookup),
+        "text/plain",
+        value,
+        forwarding_options(&trace.forward_headers()),
+    )
+    .await
+    {
+        Ok(response) => {
+            return Ok(ReplicatedResponse {
+                value: String::from(value),
+                replicas_answered: replicas_answered_header(&response),
+                trace: trace.forwarded_path(&local, started, &response),
+                lookup_hops: asked + lookup_trace::downstream_hops(&response) + 1,
+            })
+        }
+        Err(node_connection_error) => match node_connection_error {
+            // Pass on why the owner refused the write, such as too few acknowledgements
+            http_connect::NodeConnectionError::BadStatus(http_response) => {
+                return Err(status::Custom(
+                    Status::from_code(http_response.status_code as u16)
+                        .unwrap_or(Status::FailedDependency),
+                    String::from(http_response.as_str().unwrap_or("Forwarded request failed")),
+                ))
+            }
+            node_connection_error => {
+                let error_message = format!(
+                    "Could not forward request to {}:{}, {}",
+                    forward_node.hostname, forward_node.port, node_connection_error
+                );
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::FailedDependency, error_message));
+            }
+        },
+    };
+}
+
+// endpoint to remove a key-value pair, returning the removed value
+#[delete("/storage/<key>?<lookup>")]
+async fn delete_storage(
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    key: &str,
+    lookup: Option<LookupMode>,
+) -> Result<String, Custom<String>> {
+    let hashed_location: Identifier = key_to_location(key);
+
+    // Other nodes may take a while to answer, so they are not contacted while holding the lock
+    let (successor_list, is_owner) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
+
+        let is_owner =
+            is_location_in_range(hashed_location, config.local.position, config.local.range);
+        config
+            .metrics
+            .record_storage_operation(StorageOperation::Delete, !is_owner);
+        (config.successor_list.clone(), is_owner)
+    };
+
+    if is_owner {
+        // A tombstone takes the place of the value, so replicas that miss the delete cannot bring it back
+        let (deleted, tombstone) = {
+            let config = node_config.read().expect("RWLock is poisoned");
+            let deleted = config.storage.delete_next_version(key);
+            (deleted, config.storage.get(key))
+        };
+
+        match deleted {
+            Ok(Some(entry)) => {
+                if let Some(tombstone) = tombstone.filter(|entry| entry.is_deleted()) {
+                    let acknowledged = write_to_replicas(&successor_list, key, &tombstone).await;
+                    if acknowledged < successor_list.len() {
+                        println!(
+                            "Only {} of {} replicas stored the deletion of {}",
+                            acknowledged,
+                            successor_list.len(),
+                            key
+                        );
+                    }
+                }
+                return Ok(entry.value);
+            }
+            Ok(None) => {
+                return Err(status::Custom(
+                    Status::NotFound,
+                    String::from("Key not found"),
+                ))
+            }
+            Err(err) => {
+                let error_message = format!("Could not persist deletion. Error: {}", err);
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::InternalServerError, error_message));
             }
         }
     }
 
-    let forward_request_response = match http_connect::get_from_node(
+    // Early returns for cases where key is under over jurisdiction, so if we get here we need to forward the request
+    println!("Forwarding request!");
+    let (lookup, first_hop) = forwarding_start(node_config, hashed_location, lookup);
+    let (forward_node, _asked) = forwarding_target(first_hop, hashed_location, lookup).await?;
+
+    let forward_request_response = match http_connect::write_body_to_node_with_options(
+        http_connect::WriteOperations::Delete,
         &forward_node.hostname,
         forward_node.port,
-        &format!("storage/{}", key),
-    ) {
+        &storage_path(key, None, lookup),
+        "text/plain",
+        "",
+        forwarding_options(&[]),
+    )
+    .await
+    {
         Ok(response) => response,
         Err(node_connection_error) => {
-            if node_connection_error.connection_established
-                && node_connection_error
-                    .http_response
-                    .is_some_and(|http_response| http_response.status_code == 404)
-            {
+            if node_connection_error.status_code() == Some(404) {
                 return Err(status::Custom(
                     Status::NotFound,
                     String::from("Key not found"),
                 ));
             } else {
-                let error_message =
-                    String::from("Could not connect to successor to forward request.");
+                let error_message = format!(
+                    "Could not forward request to {}:{}, {}",
+                    forward_node.hostname, forward_node.port, node_connection_error
+                );
                 println!("{}", &error_message);
                 return Err(status::Custom(Status::FailedDependency, error_message));
             }
         }
-    };
+    };
+
+    return Ok(String::from(
+        forward_request_response.as_str().expect("No body found"),
+    ));
+}
+
+// Replica endpoints skip the range check, as replicas are by definition stored outside our own range
+#[get("/storage/<key>/replica")]
+fn get_storage_replica(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    key: &str,
+) -> Result<Json<Entry>, Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    match config.storage.get(key) {
+        Some(entry) => return Ok(Json(entry)),
+        None => {
+            return Err(status::Custom(
+                Status::NotFound,
+                String::from("Key not found"),
+            ))
+        }
+    };
+}
+
+#[put("/storage/<key>/replica", data = "<entry>")]
+fn put_storage_replica(
+    _authorized: Authorized,
+    _ring_member: RingMember,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    key: &str,
+    entry: Json<Entry>,
+) -> Result<(), Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    // Writes can arrive out of order, an older version must never replace a newer one
+    match config.storage.put_if_newer(key, entry.0) {
+        Ok(_stored) => return Ok(()),
+        Err(err) => {
+            let error_message = format!("Could not persist replica. Error: {}", err);
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::InternalServerError, error_message));
+        }
+    }
+}
+
+#[delete("/storage/<key>/replica")]
+fn delete_storage_replica(
+    _authorized: Authorized,
+    _ring_member: RingMember,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    key: &str,
+) -> Result<String, Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    match config.storage.delete(key) {
+        Ok(Some(entry)) => return Ok(entry.value),
+        Ok(None) => {
+            return Err(status::Custom(
+                Status::NotFound,
+                String::from("Key not found"),
+            ))
+        }
+        Err(err) => {
+            let error_message = format!("Could not persist deletion. Error: {}", err);
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::InternalServerError, error_message));
+        }
+    }
+}
+
+// First step of handing keys over to a joining node, returns a copy of every key in the requested range
+#[post("/storage/handoff", data = "<key_range>")]
+fn post_storage_handoff(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    key_range: Json<KeyRange>,
+) -> Result<Json<Vec<KeyValuePair>>, Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    let entries = entries_in_range(&config, &key_range.0);
+    println!("Handing off {} keys", entries.len());
+
+    return Ok(Json(entries));
+}
+
+// Second step of the handoff, called once the new owner is in the ring.
+// Removes every key in the range and returns them, so writes that arrived after the copy are not lost.
+#[post("/storage/handoff/release", data = "<key_range>")]
+fn post_storage_handoff_release(
+    _authorized: Authorized,
+    _ring_member: RingMember,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    key_range: Json<KeyRange>,
+) -> Result<Json<Vec<KeyValuePair>>, Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    let entries = entries_in_range(&config, &key_range.0);
+    for entry in entries.iter() {
+        if let Err(err) = config.storage.delete(&entry.key) {
+            let error_message = format!("Could not release key {}. Error: {}", entry.key, err);
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::InternalServerError, error_message));
+        }
+    }
+    println!("Released {} keys", entries.len());
 
-    return Ok(String::from(
-        forward_request_response.as_str().expect("No body found"),
-    ));
+    return Ok(Json(entries));
 }
 
-// endpoint to store a key-value pair
-#[put("/storage/<key>", format = "text", data = "<value>")]
-fn put_storage(
+// Receiving side of a leaving node handing over its keys, only answers once every key is stored
+#[post("/storage/handoff/accept", data = "<entries>")]
+fn post_storage_handoff_accept(
+    _authorized: Authorized,
+    _ring_member: RingMember,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
-    key: &str,
-    value: &str,
+    entries: Json<Vec<KeyValuePair>>,
 ) -> Result<String, Custom<String>> {
     let config = node_config.read().expect("RWLock is poisoned");
 
@@ -292,61 +1498,23 @@ fn put_storage(
         ));
     }
 
-    let hashed_location: u16 = key_to_location(key);
-
-    if is_location_in_range(hashed_location, config.local.position, config.local.range) {
-        config.storage.store(key, value);
-        return Ok(String::from(value));
-    }
-
-    // Early returns for cases where key is under over jurisdiction, so if we get here we need to forward the request
-    println!("Forwarding request!");
-
-    let mut forward_node_distance =
-        shortest_distance_on_circumference(config.local.position, hashed_location).abs();
-
-    let mut forward_node =
-        if shortest_distance_on_circumference(config.local.position, hashed_location) < 0 {
-            config
-                .precessor
-                .as_ref()
-                .expect("Could not forward, node has no successor")
-        } else {
-            config
-                .successor
-                .as_ref()
-                .expect("Could not forward, node has no successor")
-        };
-
-    // See if the key is closer to any node in the finger table
-    if config.finger_table.len() > 0 {
-        for node in config.finger_table.iter() {
-            if shortest_distance_on_circumference(node.position, hashed_location).abs()
-                < forward_node_distance
-            {
-                forward_node = node;
-                forward_node_distance =
-                    shortest_distance_on_circumference(node.position, hashed_location).abs();
-            }
+    for entry in entries.iter() {
+        // A key written to us directly since it was handed off is newer than the copy
+        if let Err(err) = config.storage.put_if_newer(&entry.key, entry.entry.clone()) {
+            let error_message = format!("Could not store key {}. Error: {}", entry.key, err);
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::InternalServerError, error_message));
         }
     }
+    println!("Accepted {} handed off keys", entries.len());
 
-    let forward_request_response = http_connect::write_body_to_node(
-        http_connect::WriteOperations::Post,
-        &forward_node.hostname,
-        forward_node.port,
-        &format!("storage/{}", key),
-        "text/plain",
-        value,
-    );
-
-    forward_request_response.expect("Could not forward request");
-
-    return Ok(String::from(value));
+    return Ok(format!("Accepted {} keys", entries.len()));
 }
 
 #[get("/ring/precessor")]
 fn get_precessor(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
 ) -> Result<Json<Node>, Custom<String>> {
     let config = node_config.read().expect("RWLock is poisoned");
@@ -369,6 +1537,8 @@ fn get_precessor(
 
 #[get("/ring/successor")]
 fn get_successor(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
 ) -> Result<Json<Node>, Custom<String>> {
     let config = node_config.read().expect("RWLock is poisoned");
@@ -389,8 +1559,30 @@ fn get_successor(
     }
 }
 
+#[get("/ring/neighbour_status")]
+fn get_neighbour_status(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<Json<Vec<NeighbourHealth>>, Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    return Ok(Json(config.neighbour_health.values().cloned().collect()));
+}
+
 #[get("/ring/local")]
-fn get_local(node_config: &State<Arc<RwLock<NodeConfig>>>) -> Result<Json<Node>, Custom<String>> {
+fn get_local(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<Json<Node>, Custom<String>> {
     let config = node_config.read().expect("RWLock is poisoned");
 
     if config.is_crashed() {
@@ -405,6 +1597,8 @@ fn get_local(node_config: &State<Arc<RwLock<NodeConfig>>>) -> Result<Json<Node>,
 
 #[put("/ring/precessor", data = "<new_precessor>")]
 fn put_precessor(
+    _authorized: Authorized,
+    _ring_member: RingMember,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
     new_precessor: Json<Node>,
 ) -> Result<(), Custom<String>> {
@@ -424,6 +1618,8 @@ fn put_precessor(
 
 #[put("/ring/successor", data = "<new_successor>")]
 fn put_successor(
+    _authorized: Authorized,
+    _ring_member: RingMember,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
     new_successor: Json<Node>,
 ) -> Result<(), Custom<String>> {
@@ -446,11 +1642,47 @@ fn put_successor(
 
     println!("{:?}", new_successor);
 
-    config.successor = Some(new_successor.0.clone());
-    if new_successor.0.position < config.local.position {
-        config.local.range = (RING_SIZE - config.local.position) + new_successor.0.position;
-    } else {
-        config.local.range = new_successor.0.position - config.local.position;
+    set_successor(&mut config, new_successor.0);
+
+    Ok(())
+}
+
+// Called by a node that believes it is our precessor, as in Chord we accept it if it is closer than our current one
+#[put("/ring/notify", data = "<candidate>")]
+fn put_notify(
+    _authorized: Authorized,
+    _ring_member: RingMember,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    candidate: Json<Node>,
+) -> Result<(), Custom<String>> {
+    let mut config = node_config.write().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    let is_closer = match config.precessor.as_ref() {
+        None => true,
+        Some(precessor) if is_precessor_dead(&config, precessor) => true,
+        Some(precessor) => {
+            (precessor.hostname == config.local.hostname && precessor.port == config.local.port)
+                || is_location_between(
+                    candidate.position,
+                    precessor.position,
+                    config.local.position,
+                )
+        }
+    };
+
+    if is_closer {
+        println!(
+            "Precessor updated by notify to {}:{}",
+            candidate.hostname, candidate.port
+        );
+        config.precessor = Some(candidate.0);
     }
 
     Ok(())
@@ -458,6 +1690,8 @@ fn put_successor(
 
 #[put("/ring/local", data = "<new_local>")]
 fn put_local(
+    _authorized: Authorized,
+    _ring_member: RingMember,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
     new_local: Json<Node>,
 ) -> Result<(), Custom<String>> {
@@ -475,10 +1709,13 @@ fn put_local(
     Ok(())
 }
 
-#[get("/ring/finger_table")]
-fn get_finger_table(
+// The local node along with the number of keys it owns, used to report the effect of a rebalance
+#[get("/ring/load")]
+fn get_load(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
-) -> Result<Json<Vec<Node>>, Custom<String>> {
+) -> Result<Json<NodeLoad>, Custom<String>> {
     let config = node_config.read().expect("RWLock is poisoned");
 
     if config.is_crashed() {
@@ -488,16 +1725,27 @@ fn get_finger_table(
         ));
     }
 
-    return Ok(Json(config.finger_table.clone()));
+    let owned_range = KeyRange {
+        position: config.local.position,
+        range: config.local.range,
+    };
+
+    return Ok(Json(NodeLoad {
+        node: config.local.clone(),
+        keys: entries_in_range(&config, &owned_range)
+            .iter()
+            .filter(|pair| !pair.entry.is_deleted())
+            .count(),
+    }));
 }
 
-#[put("/ring/calculate_finger_table", data = "<finger_table_info>")]
-fn calculate_finger_table(
+#[get("/ring/finger_table")]
+fn get_finger_table(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
-    finger_table_info: Json<FingerTableInformation>,
-) -> Result<String, Custom<String>> {
-    let mut config = node_config.write().expect("RWLock is poisoned");
-    println!("Calculate finger table");
+) -> Result<Json<Vec<Node>>, Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
 
     if config.is_crashed() {
         return Err(status::Custom(
@@ -506,24 +1754,104 @@ fn calculate_finger_table(
         ));
     }
 
-    config.finger_table.clear();
+    return Ok(Json(config.finger_table.clone()));
+}
 
-    // Add local node to finger table, and all other nodes in the network
-    let mut complete_node_list = vec![config.local.clone()];
-    if !config.connected {
-        let error_message = String::from("Node is not connected to a network");
-        return Err(status::Custom(Status::FailedDependency, error_message));
+async fn build_finger_table(
+    local: &Node,
+    successor: Node,
+    size: usize,
+    mode: FingerTableMode,
+) -> Result<Vec<Node>, Custom<String>> {
+    match mode {
+        FingerTableMode::EvenlySpaced => {
+            build_evenly_spaced_finger_table(local, successor, size).await
+        }
+        FingerTableMode::Chord => build_chord_finger_table(local, successor, size).await,
+    }
+}
+
+// For i in 0..size, looks up the node responsible for position + 2^i through our successor.
+// Entries pointing back at ourself are skipped, and each node is only added once.
+async fn build_chord_finger_table(
+    local: &Node,
+    successor: Node,
+    size: usize,
+) -> Result<Vec<Node>, Custom<String>> {
+    if size == 0 || size > identifier::ring_bits() as usize {
+        let error_message = format!(
+            "Chord finger table size must be between 1 and {}.",
+            identifier::ring_bits()
+        );
+        println!("{}", &error_message);
+        return Err(status::Custom(Status::BadRequest, error_message));
+    }
+
+    let mut finger_table: Vec<Node> = vec![];
+    for i in 0..size {
+        let location = local.position + Identifier::power_of_two(i as u32);
+
+        if is_location_in_range(location, local.position, local.range) {
+            continue;
+        }
+
+        let finger = match http_connect::get_from_node(
+            &successor.hostname,
+            successor.port,
+            &format!("ring/find_successor/{}", location),
+        )
+        .await
+        {
+            Err(_err) => {
+                let error_message = format!("Could not look up finger for location {}.", location);
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::FailedDependency, error_message));
+            }
+            Ok(response) => match response.json::<Node>() {
+                Err(_err) => {
+                    let error_message = String::from("Unable to parse looked up finger from JSON.");
+                    println!("{}", &error_message);
+                    return Err(status::Custom(Status::FailedDependency, error_message));
+                }
+                Ok(parsed) => parsed,
+            },
+        };
+
+        let is_known = finger_table
+            .iter()
+            .any(|node| node.hostname == finger.hostname && node.port == finger.port);
+        if !is_known && (finger.hostname != local.hostname || finger.port != local.port) {
+            println!(
+                "Adding finger {} for location {}",
+                finger.position, location
+            );
+            finger_table.push(finger);
+        }
     }
-    let mut current_node = config.successor.clone().expect("No successor");
 
-    while current_node.hostname != config.local.hostname || current_node.port != config.local.port {
+    return Ok(finger_table);
+}
+
+// Walks the whole ring from our successor and picks `size` evenly spaced nodes, starting with ourself
+async fn build_evenly_spaced_finger_table(
+    local: &Node,
+    successor: Node,
+    size: usize,
+) -> Result<Vec<Node>, Custom<String>> {
+    // Add local node to finger table, and all other nodes in the network
+    let mut complete_node_list = vec![local.clone()];
+    let mut current_node = successor;
+
+    while current_node.hostname != local.hostname || current_node.port != local.port {
         complete_node_list.push(current_node.clone());
 
         let get_successor_response = match http_connect::get_from_node(
             &current_node.hostname,
             current_node.port,
             "ring/successor",
-        ) {
+        )
+        .await
+        {
             Err(_err) => {
                 let error_message = String::from("Could not connect to node to get successor.");
                 println!("{}", &error_message);
@@ -543,29 +1871,316 @@ fn calculate_finger_table(
         };
     }
 
-    if complete_node_list.len() < usize::from(finger_table_info.size) {
-        let error_message = String::from("Not enough nodes in network to calculate finger table.");
-        println!("{}", &error_message);
-        return Err(status::Custom(Status::BadRequest, error_message));
-    } else if complete_node_list.len() == usize::from(finger_table_info.size) {
-        println!("Creating fully connected finger table, with all nodes in network.");
-    }
+    if complete_node_list.len() < size {
+        let error_message = String::from("Not enough nodes in network to calculate finger table.");
+        println!("{}", &error_message);
+        return Err(status::Custom(Status::BadRequest, error_message));
+    } else if complete_node_list.len() == size {
+        println!("Creating fully connected finger table, with all nodes in network.");
+    }
+
+    println!("Node list length: {}", complete_node_list.len());
+
+    if size == 0 {
+        let error_message = String::from("Finger table size cannot be zero.");
+        println!("{}", &error_message);
+        return Err(status::Custom(Status::BadRequest, error_message));
+    }
+
+    let mut finger_table = vec![];
+    let step = complete_node_list.len() / size;
+    for i in 0..size {
+        let index = (i * step) % complete_node_list.len();
+        println!("Adding node: {}", index);
+        finger_table.push(complete_node_list[index].clone());
+    }
+
+    return Ok(finger_table);
+}
+
+#[put("/ring/calculate_finger_table", data = "<finger_table_info>")]
+async fn calculate_finger_table(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    finger_table_info: Json<FingerTableInformation>,
+) -> Result<String, Custom<String>> {
+    println!("Calculate finger table");
+
+    // Lookups may be routed back through us, so the lock is not held while the table is built
+    let (local, successor) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
+
+        if !config.connected {
+            let error_message = String::from("Node is not connected to a network");
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+
+        (
+            config.local.clone(),
+            config.successor.clone().expect("No successor"),
+        )
+    };
+    let size = usize::from(finger_table_info.size);
+
+    let finger_table = build_finger_table(&local, successor, size, finger_table_info.mode).await?;
+
+    let mut config = node_config.write().expect("RWLock is poisoned");
+    config.finger_table = finger_table;
+
+    // Remembered so the fix-fingers task can rebuild the same kind of table
+    config.finger_table_size = size;
+    config.finger_table_mode = finger_table_info.mode;
+
+    return Ok(String::from("Finger table calculated"));
+}
+
+// Returns the node whose range holds the location, forwarding towards it like storage requests
+#[get("/ring/find_successor/<location>")]
+async fn get_find_successor(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    location: Identifier,
+) -> Result<Json<Node>, Custom<String>> {
+    let forward_node = {
+        let config = node_config.read().expect("RWLock is poisoned");
+
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
+
+        if is_location_in_range(location, config.local.position, config.local.range) {
+            return Ok(Json(config.local.clone()));
+        }
+
+        closest_node_to_location(&config, location).clone()
+    };
+
+    match http_connect::get_from_node(
+        &forward_node.hostname,
+        forward_node.port,
+        &format!("ring/find_successor/{}", location),
+    )
+    .await
+    {
+        Err(_err) => {
+            let error_message = String::from("Could not forward lookup.");
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+        Ok(response) => match response.json::<Node>() {
+            Err(_err) => {
+                let error_message = String::from("Unable to parse looked up node from JSON.");
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::FailedDependency, error_message));
+            }
+            Ok(node) => return Ok(Json(node)),
+        },
+    }
+}
+
+// Step of an iterative lookup: tells whether we hold the location, and if not which node to ask next
+#[get("/ring/next_hop/<location>")]
+fn get_next_hop(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    location: Identifier,
+) -> Result<Json<NextHop>, Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    if is_location_in_range(location, config.local.position, config.local.range) {
+        return Ok(Json(NextHop {
+            owner: true,
+            node: config.local.clone(),
+        }));
+    }
+
+    return Ok(Json(NextHop {
+        owner: false,
+        node: closest_node_to_location(&config, location).clone(),
+    }));
+}
+
+// Follows the route a storage request for the key would take, and returns every node it passed through
+#[get("/lookup/<key>?<ttl>&<mode>")]
+async fn get_lookup(
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    key: &str,
+    ttl: Option<u16>,
+    mode: Option<LookupMode>,
+    client: ClientRequest,
+) -> Result<Json<LookupTrace>, Custom<String>> {
+    let started = Instant::now();
+    let hashed_location: Identifier = key_to_location(key);
+    let ttl = ttl.unwrap_or(DEFAULT_LOOKUP_TTL);
+
+    let (forward_node, local, mode) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
+
+        if is_location_in_range(hashed_location, config.local.position, config.local.range) {
+            return Ok(Json(LookupTrace::ending_at(
+                key,
+                hashed_location,
+                &config.local,
+                started,
+                true,
+            )));
+        }
+
+        // Out of hops, most likely the lookup is going in circles. Stop here so the path so far can be inspected.
+        if ttl <= 1 {
+            println!("Lookup for {} ran out of hops", key);
+            return Ok(Json(LookupTrace::ending_at(
+                key,
+                hashed_location,
+                &config.local,
+                started,
+                false,
+            )));
+        }
+
+        (
+            closest_node_to_location(&config, hashed_location).clone(),
+            config.local.clone(),
+            mode.unwrap_or(config.lookup_mode),
+        )
+    };
+
+    if mode == LookupMode::Iterative {
+        match lookup::find_owner_iteratively(forward_node, hashed_location, ttl - 1).await {
+            Err(error_message) => {
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::FailedDependency, error_message));
+            }
+            Ok(path) => {
+                record_lookup_hops(node_config, &client, path.len());
+                return Ok(Json(LookupTrace::iterative(
+                    key,
+                    hashed_location,
+                    &local,
+                    started,
+                    path,
+                )));
+            }
+        }
+    }
+
+    match http_connect::get_from_node(
+        &forward_node.hostname,
+        forward_node.port,
+        &format!("lookup/{}?ttl={}&mode=recursive", key, ttl - 1),
+    )
+    .await
+    {
+        Err(_err) => {
+            let error_message = String::from("Could not forward lookup.");
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+        Ok(response) => match response.json::<LookupTrace>() {
+            Err(_err) => {
+                let error_message = String::from("Unable to parse lookup trace from JSON.");
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::FailedDependency, error_message));
+            }
+            Ok(mut trace) => {
+                trace.prepend(&local, started);
+                record_lookup_hops(node_config, &client, trace.hop_count - 1);
+                return Ok(Json(trace));
+            }
+        },
+    }
+}
+
+#[get("/ring/successor_list")]
+fn get_successor_list(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<Json<Vec<Node>>, Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    return Ok(Json(config.successor_list.clone()));
+}
 
-    println!("Node list length: {}", complete_node_list.len());
+#[put("/ring/calculate_successor_list")]
+async fn calculate_successor_list(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<String, Custom<String>> {
+    println!("Calculate successor list");
 
-    let size = usize::from(finger_table_info.size);
-    if size == 0 || size > complete_node_list.len() {
-        panic!("Finger table size cannot be zero or greater than the number of nodes.");
-    }
+    let (local, successor, wanted) = {
+        let config = node_config.read().expect("RWLock is poisoned");
 
-    let step = complete_node_list.len() / size;
-    for i in 0..size {
-        let index = (i * step) % complete_node_list.len();
-        println!("Adding node: {}", index);
-        config.finger_table.push(complete_node_list[index].clone());
-    }
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
 
-    return Ok(String::from("Finger table calculated"));
+        if !config.connected {
+            let error_message = String::from("Node is not connected to a network");
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+
+        (
+            config.local.clone(),
+            config.successor.clone().expect("No successor"),
+            config.replication_factor() - 1,
+        )
+    };
+
+    let successor_list = match build_successor_list(&local, successor, wanted).await {
+        Ok(successor_list) => successor_list,
+        Err(error_message) => {
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+    };
+
+    let mut config = node_config.write().expect("RWLock is poisoned");
+    config.successor_list = successor_list;
+
+    return Ok(format!(
+        "Successor list calculated with {} nodes",
+        config.successor_list.len()
+    ));
 }
 
 // Endpoint to get information about the network
@@ -643,8 +2258,57 @@ fn get_node_info(
     }));
 }
 
+// Calls turned away because they came from a node of another network
+#[get("/node-info/rejected_calls")]
+fn get_rejected_calls(node_config: &State<Arc<RwLock<NodeConfig>>>) -> Json<RejectedCalls> {
+    return Json(
+        node_config
+            .read()
+            .expect("RWLock is poisoned")
+            .rejected_calls
+            .clone(),
+    );
+}
+
+// Counters and gauges of the node for Prometheus to scrape
+#[get("/metrics")]
+fn get_metrics(
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<(ContentType, String), Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    let content_type = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+    return Ok((content_type, metrics::render(&config)));
+}
+
+// Connections to other nodes are shared by all virtual nodes of the process, so are their stats
+#[get("/node-info/connections")]
+fn get_connection_stats(
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<Json<PoolStats>, Custom<String>> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    return Ok(Json(connection_pool::stats()));
+}
+
 #[put("/network/initialize", data = "<network_information>")]
 fn put_network_initialize(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
     network_information: Json<SuppliedNetworkInformation>,
 ) -> Result<String, Custom<String>> {
@@ -664,16 +2328,36 @@ fn put_network_initialize(
         ));
     }
 
+    if network_information.replication_factor < 1 {
+        return Err(status::Custom(
+            Status::BadRequest,
+            String::from("Replication factor must be at least 1"),
+        ));
+    }
+
     config.connected = true;
     config.network = Some(Network {
         network_id: network_information.network_id.clone(),
+        replication_factor: network_information.replication_factor,
+        ring_bits: identifier::ring_bits(),
     });
 
-    config.local.position = 0;
-    config.local.range = RING_SIZE;
+    config.local.position = Identifier::zero();
+    config.local.range = Identifier::whole_ring();
     config.precessor = Some(config.local.clone());
     config.successor = Some(config.local.clone());
 
+    virtual_nodes::join_siblings(
+        &config.virtual_nodes,
+        JoinRequest {
+            existing_node: SuppliedNode {
+                hostname: config.local.hostname.clone(),
+                port: config.local.port,
+            },
+            strategy: config.join_strategy,
+        },
+    );
+
     return Ok(format!(
         "Initialized network with network_id: {}",
         config
@@ -684,31 +2368,38 @@ fn put_network_initialize(
 }
 
 #[get("/network/longest_range")]
-fn get_network_longest_range(
+async fn get_network_longest_range(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
 ) -> Result<Json<LongestRangeResponse>, Custom<String>> {
-    let config = node_config.read().expect("RWLock is poisoned");
+    let (longest_range_request, successor) = {
+        let config = node_config.read().expect("RWLock is poisoned");
 
-    if config.is_crashed() {
-        return Err(status::Custom(
-            Status::ServiceUnavailable,
-            String::from("Node is crashed"),
-        ));
-    }
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
 
-    let longest_range_request = LongestRangeRequest {
-        started_by: config.local.clone(),
-    };
+        let longest_range_request = LongestRangeRequest {
+            started_by: config.local.clone(),
+        };
 
-    if !config.connected {
-        let error_message = String::from(
-            "Node is not in a network and therefore can't provide information on longest range.",
-        );
-        println!("{}", &error_message);
-        return Err(status::Custom(Status::FailedDependency, error_message));
-    }
+        if !config.connected {
+            let error_message = String::from(
+                "Node is not in a network and therefore can't provide information on longest range.",
+            );
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
 
-    let successor = config.successor.as_ref().expect("No successor");
+        (
+            longest_range_request,
+            config.successor.clone().expect("No successor"),
+        )
+    };
 
     let upstream_response = match http_connect::write_json_to_node(
         http_connect::WriteOperations::Post,
@@ -716,7 +2407,9 @@ fn get_network_longest_range(
         successor.port,
         "network/longest_range",
         &longest_range_request,
-    ) {
+    )
+    .await
+    {
         Ok(response) => response,
         Err(_err) => {
             return Err(status::Custom(
@@ -740,36 +2433,40 @@ fn get_network_longest_range(
 }
 
 #[post("/network/longest_range", data = "<longest_range_request>")]
-fn post_network_longest_range(
+async fn post_network_longest_range(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
     longest_range_request: Json<LongestRangeRequest>,
 ) -> Result<Json<LongestRangeResponse>, Custom<String>> {
-    let config = node_config.read().expect("RWLock is poisoned");
+    let (local, successor) = {
+        let config = node_config.read().expect("RWLock is poisoned");
 
-    if config.is_crashed() {
-        return Err(status::Custom(
-            Status::ServiceUnavailable,
-            String::from("Node is crashed"),
-        ));
-    }
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
 
-    if !config.connected {
-        let error_message = String::from(
-            "Node is not in a network and therefore can't provide information on longest range.",
-        );
-        println!("{}", &error_message);
-        return Err(status::Custom(Status::FailedDependency, error_message));
-    }
+        if !config.connected {
+            let error_message = String::from(
+                "Node is not in a network and therefore can't provide information on longest range.",
+            );
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+
+        (config.local.clone(), config.successor.clone())
+    };
 
-    if longest_range_request.0.started_by.hostname == config.local.hostname
-        && longest_range_request.0.started_by.port == config.local.port
+    if longest_range_request.0.started_by.hostname == local.hostname
+        && longest_range_request.0.started_by.port == local.port
     {
-        let longest_range_response = LongestRangeResponse {
-            holder: config.local.clone(),
-        };
+        let longest_range_response = LongestRangeResponse { holder: local };
         return Ok(Json(longest_range_response));
     } else {
-        let successor = config.successor.as_ref().expect("No successor");
+        let successor = successor.expect("No successor");
 
         let upstream_response = match http_connect::write_json_to_node(
             http_connect::WriteOperations::Post,
@@ -777,7 +2474,9 @@ fn post_network_longest_range(
             successor.port,
             "network/longest_range",
             longest_range_request.0,
-        ) {
+        )
+        .await
+        {
             Ok(response) => response,
             Err(_err) => {
                 return Err(status::Custom(
@@ -797,21 +2496,70 @@ fn post_network_longest_range(
             Ok(longest_range_upstream) => longest_range_upstream,
         };
 
-        if longest_range_upstream.holder.range >= config.local.range {
+        if longest_range_upstream.holder.range.range_length() >= local.range.range_length() {
             return Ok(Json(longest_range_upstream));
         } else {
-            let longest_range_response = LongestRangeResponse {
-                holder: config.local.clone(),
-            };
+            let longest_range_response = LongestRangeResponse { holder: local };
             return Ok(Json(longest_range_response));
         }
     }
 }
 
 #[get("/network/request_join_network_information")]
-fn get_network_request_join(
+async fn get_network_request_join(
+    authorized: Authorized,
+    same_network: SameNetwork,
     node_config: &State<Arc<RwLock<NodeConfig>>>,
 ) -> Result<Json<JoinNetworkInformation>, Custom<String>> {
+    let network = {
+        let config = node_config.read().expect("RWLock is poisoned");
+
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
+
+        if !config.connected {
+            let error_message = String::from(
+                "Node is not in a network and therefore can't provide information to join.",
+            );
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+
+        config
+            .network
+            .as_ref()
+            .expect("Node was connected, but had no network")
+            .clone()
+    };
+
+    let longest_range: LongestRangeResponse =
+        match get_network_longest_range(authorized, same_network, node_config).await {
+            Ok(range) => range.0,
+            Err(err) => {
+                let error_message =
+                    format!("Could not get longest range in network. Error: {}", err.1);
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::FailedDependency, error_message));
+            }
+        };
+
+    let join_network_information = JoinNetworkInformation {
+        network,
+        longest_range: longest_range,
+    };
+
+    return Ok(Json(join_network_information));
+}
+
+// Settings of the network we are part of, without the ring walk of request_join_network_information
+#[get("/network/description")]
+fn get_network_description(
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<Json<Network>, Custom<String>> {
     let config = node_config.read().expect("RWLock is poisoned");
 
     if config.is_crashed() {
@@ -821,54 +2569,270 @@ fn get_network_request_join(
         ));
     }
 
-    if !config.connected {
-        let error_message = String::from(
-            "Node is not in a network and therefore can't provide information to join.",
-        );
-        println!("{}", &error_message);
-        return Err(status::Custom(Status::FailedDependency, error_message));
+    match config.network.clone() {
+        Some(network) => return Ok(Json(network)),
+        None => {
+            let error_message = String::from(
+                "Node is not in a network and therefore can't provide information to join.",
+            );
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
     }
+}
 
-    let longest_range: LongestRangeResponse = match get_network_longest_range(node_config) {
-        Ok(range) => range.0,
-        Err(err) => {
-            let error_message = format!("Could not get longest range in network. Error: {}", err.1);
+#[put("/network/join", data = "<join_request>")]
+async fn put_network_join(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+    join_request: Json<JoinRequest>,
+) -> Result<String, Custom<String>> {
+    if node_config.read().expect("RWLock is poisoned").is_crashed() {
+        return Err(status::Custom(
+            Status::ServiceUnavailable,
+            String::from("Node is crashed"),
+        ));
+    }
+
+    let joined = join_network(
+        node_config,
+        &join_request.existing_node,
+        join_request.strategy,
+    )
+    .await?;
+
+    let mut config = node_config.write().expect("RWLock is poisoned");
+
+    // Later rejoins after a crash pick their position the same way
+    config.join_strategy = join_request.strategy;
+
+    virtual_nodes::join_siblings(
+        &config.virtual_nodes,
+        JoinRequest {
+            existing_node: SuppliedNode {
+                hostname: config.local.hostname.clone(),
+                port: config.local.port,
+            },
+            strategy: join_request.strategy,
+        },
+    );
+
+    return Ok(joined);
+}
+
+// Joins the network existing_node is part of. The strategy decides our position, and thereby which
+// node currently holds it and hands part of its range over to us. The lock is only taken in between
+// requests to other nodes, as the ring may route requests back to us while we join.
+async fn join_network(
+    node_config: &RwLock<NodeConfig>,
+    existing_node: &SuppliedNode,
+    strategy: JoinStrategy,
+) -> Result<String, Custom<String>> {
+    let mut local = node_config
+        .read()
+        .expect("RWLock is poisoned")
+        .local
+        .clone();
+
+    let (network, holder, position) = match strategy {
+        JoinStrategy::SplitLongestRange => split_longest_range(existing_node).await?,
+        JoinStrategy::Hash => hashed_position(&local, existing_node).await?,
+    };
+
+    let get_successor_response =
+        match http_connect::get_from_node(&holder.hostname, holder.port, "ring/successor").await {
+            Ok(response) => response,
+            Err(_err) => {
+                return Err(status::Custom(
+                    Status::FailedDependency,
+                    String::from("Could not get successor from holder of our position."),
+                ))
+            }
+        };
+
+    let recieved_successor = match get_successor_response.json::<Node>() {
+        Err(_err) => {
+            let error_message =
+                String::from("Unable to parse received network information from JSON.");
             println!("{}", &error_message);
             return Err(status::Custom(Status::FailedDependency, error_message));
         }
+        Ok(parsed) => parsed,
+    };
+
+    local.position = position;
+    local.range = recieved_successor.position - local.position;
+    println!(
+        "Successor position: {}, local position: {}",
+        recieved_successor.position, local.position
+    );
+
+    // Copy the keys we are taking over from the holder before the ring starts routing them to us
+    let handoff_range = KeyRange {
+        position: local.position,
+        range: local.range,
+    };
+    let handed_off = match http_connect::write_json_to_node(
+        http_connect::WriteOperations::Post,
+        &holder.hostname,
+        holder.port,
+        "storage/handoff",
+        &handoff_range,
+    )
+    .await
+    {
+        Ok(response) => match response.json::<Vec<KeyValuePair>>() {
+            Ok(entries) => entries,
+            Err(_err) => {
+                let error_message = String::from("Unable to parse handed off keys from JSON.");
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::FailedDependency, error_message));
+            }
+        },
+        Err(_err) => {
+            return Err(status::Custom(
+                Status::FailedDependency,
+                String::from("Could not get keys from holder of our position."),
+            ));
+        }
+    };
+
+    let wanted = {
+        let mut config = node_config.write().expect("RWLock is poisoned");
+
+        // A node rejoining with a data directory may already hold newer versions of some keys
+        for entry in handed_off.iter() {
+            if let Err(err) = config.storage.put_if_newer(&entry.key, entry.entry.clone()) {
+                let error_message = format!("Could not store handed off keys. Error: {}", err);
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::InternalServerError, error_message));
+            }
+        }
+        println!("Received {} keys from holder", handed_off.len());
+
+        config.connected = true;
+        config.network = Some(network);
+        config.local = local.clone();
+        config.successor = Some(recieved_successor.clone());
+        config.precessor = Some(holder.clone());
+        config.replication_factor() - 1
+    };
+
+    match http_connect::write_json_to_node(
+        http_connect::WriteOperations::Put,
+        &holder.hostname,
+        holder.port,
+        "ring/successor",
+        &local,
+    )
+    .await
+    {
+        Ok(response) => response,
+        Err(_err) => {
+            return Err(status::Custom(
+                Status::FailedDependency,
+                String::from("Could not set successor of precessor"),
+            ))
+        }
+    };
+
+    match http_connect::write_json_to_node(
+        http_connect::WriteOperations::Put,
+        &recieved_successor.hostname,
+        recieved_successor.port,
+        "ring/precessor",
+        &local,
+    )
+    .await
+    {
+        Ok(response) => response,
+        Err(_err) => {
+            return Err(status::Custom(
+                Status::FailedDependency,
+                String::from("Could not set precessor of successor"),
+            ))
+        }
+    };
+
+    // Ownership has moved to us, so the holder can drop its copies. The latest values are
+    // returned, covering any write the holder accepted after the keys were copied.
+    match http_connect::write_json_to_node(
+        http_connect::WriteOperations::Post,
+        &holder.hostname,
+        holder.port,
+        "storage/handoff/release",
+        &handoff_range,
+    )
+    .await
+    {
+        Ok(response) => match response.json::<Vec<KeyValuePair>>() {
+            Ok(released) => {
+                // Writes we accepted since the holder switched its successor are newer than its copy
+                let config = node_config.read().expect("RWLock is poisoned");
+                for entry in released.iter() {
+                    if let Err(err) = config.storage.put_if_newer(&entry.key, entry.entry.clone()) {
+                        println!("Could not store released key {}. Error: {}", entry.key, err);
+                    }
+                }
+            }
+            Err(_err) => println!("Unable to parse released keys from JSON."),
+        },
+        Err(_err) => println!("Could not release handed off keys on holder, stale copies remain."),
     };
 
-    let join_network_information = JoinNetworkInformation {
-        network: config
-            .network
-            .as_ref()
-            .expect("Node was connected, but had no network")
-            .clone(),
-        longest_range: longest_range,
+    // Without a successor list our writes would not be replicated until it is recalculated
+    let successor_list = match build_successor_list(&local, recieved_successor, wanted).await {
+        Ok(successor_list) => successor_list,
+        Err(error_message) => {
+            println!(
+                "Could not build successor list after joining: {}",
+                error_message
+            );
+            vec![]
+        }
     };
 
-    return Ok(Json(join_network_information));
-}
-
-#[put("/network/join", data = "<existing_node>")]
-fn put_network_join(
-    node_config: &State<Arc<RwLock<NodeConfig>>>,
-    existing_node: Json<SuppliedNode>,
-) -> Result<String, Custom<String>> {
     let mut config = node_config.write().expect("RWLock is poisoned");
+    config.successor_list = successor_list;
 
-    if config.is_crashed() {
-        return Err(status::Custom(
-            Status::ServiceUnavailable,
-            String::from("Node is crashed"),
-        ));
+    return Ok(format!(
+        "Joined network with ID: {}",
+        config
+            .network
+            .clone()
+            .map_or(String::from("Unknown"), |network| network
+                .network_id
+                .clone())
+    ));
+}
+
+// Positions can only be read on a ring of the same size, so this is checked before anything else
+fn check_ring_bits(network: &Network) -> Result<(), Custom<String>> {
+    if network.ring_bits != identifier::ring_bits() {
+        let error_message = format!(
+            "Unable to join, the network uses {} bit positions and this node {}.",
+            network.ring_bits,
+            identifier::ring_bits()
+        );
+        println!("{}", &error_message);
+        return Err(status::Custom(Status::Conflict, error_message));
     }
 
+    return Ok(());
+}
+
+// Position in the middle of the longest range in the network, along with the node holding that range
+async fn split_longest_range(
+    existing_node: &SuppliedNode,
+) -> Result<(Network, Node, Identifier), Custom<String>> {
     let join_response = match http_connect::get_from_node(
-        &existing_node.0.hostname,
-        existing_node.0.port,
+        &existing_node.hostname,
+        existing_node.port,
         "network/request_join_network_information",
-    ) {
+    )
+    .await
+    {
         Ok(response) => response,
         Err(_err) => {
             return Err(status::Custom(
@@ -878,6 +2842,10 @@ fn put_network_join(
         }
     };
 
+    if let Ok(description) = join_response.json::<JoinNetworkDescription>() {
+        check_ring_bits(&description.network)?;
+    }
+
     let received_network_information = match join_response.json::<JoinNetworkInformation>() {
         Err(_err) => {
             let error_message =
@@ -888,174 +2856,252 @@ fn put_network_join(
         Ok(received_network_information) => received_network_information,
     };
 
-    if received_network_information.longest_range.holder.range < 2 {
+    let holder = received_network_information.longest_range.holder;
+    if holder.range.range_length() < Identifier::from_u64(2) {
         let error_message = String::from("Unable to join as network is already full.");
         println!("{}", &error_message);
         return Err(status::Custom(Status::FailedDependency, error_message));
     }
 
-    let get_successor_response = match http_connect::get_from_node(
-        &received_network_information.longest_range.holder.hostname,
-        received_network_information.longest_range.holder.port,
-        "ring/successor",
-    ) {
-        Ok(response) => response,
+    let position = holder.position + holder.range.range_length().half();
+    return Ok((received_network_information.network, holder, position));
+}
+
+// Position given by the hash of our hostname:port, along with the node currently holding it.
+// Only takes a lookup through the existing node, instead of a walk around the whole ring.
+async fn hashed_position(
+    local: &Node,
+    existing_node: &SuppliedNode,
+) -> Result<(Network, Node, Identifier), Custom<String>> {
+    let network = match http_connect::get_from_node(
+        &existing_node.hostname,
+        existing_node.port,
+        "network/description",
+    )
+    .await
+    {
         Err(_err) => {
             return Err(status::Custom(
                 Status::FailedDependency,
-                String::from("Could not get successor from holder of longest range."),
+                String::from("Unable to join node."),
             ))
         }
+        Ok(response) => match response.json::<Network>() {
+            Err(_err) => {
+                let error_message = String::from("Unable to parse received network from JSON.");
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::FailedDependency, error_message));
+            }
+            Ok(network) => network,
+        },
     };
+    check_ring_bits(&network)?;
 
-    let recieved_successor = match get_successor_response.json::<Node>() {
+    let position = key_to_location(&failure_detector::node_id(local));
+
+    let holder = match http_connect::get_from_node(
+        &existing_node.hostname,
+        existing_node.port,
+        &format!("ring/find_successor/{}", position),
+    )
+    .await
+    {
         Err(_err) => {
-            let error_message =
-                String::from("Unable to parse received network information from JSON.");
+            let error_message = format!("Could not look up holder of position {}.", position);
             println!("{}", &error_message);
             return Err(status::Custom(Status::FailedDependency, error_message));
         }
-        Ok(parsed) => parsed,
+        Ok(response) => match response.json::<Node>() {
+            Err(_err) => {
+                let error_message = String::from("Unable to parse looked up node from JSON.");
+                println!("{}", &error_message);
+                return Err(status::Custom(Status::FailedDependency, error_message));
+            }
+            Ok(holder) => holder,
+        },
     };
 
-    config.connected = true;
-    config.network = Some(received_network_information.network.clone());
+    if holder.position == position {
+        let error_message = format!(
+            "Unable to join, position {} is already taken by {}.",
+            position,
+            failure_detector::node_id(&holder)
+        );
+        println!("{}", &error_message);
+        return Err(status::Custom(Status::Conflict, error_message));
+    }
 
-    config.local.position = received_network_information.longest_range.holder.position
-        + received_network_information.longest_range.holder.range / 2;
+    return Ok((network, holder, position));
+}
 
-    if recieved_successor.position < config.local.position {
-        config.local.range = (RING_SIZE - config.local.position) + (recieved_successor.position);
-    } else {
-        config.local.range = recieved_successor.position - config.local.position;
+// Spaces all nodes evenly around the ring and moves the keys that changed owner. Meant to be run by
+// an operator while no nodes join or leave.
+#[post("/network/rebalance")]
+async fn post_network_rebalance(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<Json<RebalanceReport>, Custom<String>> {
+    // Every node is updated over HTTP, the local one included
+    let (local, successor, replication_factor) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
+
+        if !config.connected {
+            let error_message = String::from("Node is not connected to a network");
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+
+        (
+            config.local.clone(),
+            config.successor.clone().expect("No successor"),
+            config.replication_factor(),
+        )
+    };
+
+    match rebalance::rebalance(&local, &successor, replication_factor).await {
+        Ok(report) => {
+            println!("Rebalanced ring, moved {} keys", report.moved_keys);
+            return Ok(Json(report));
+        }
+        Err(error_message) => {
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
     }
-    println!(
-        "Successor position: {}, local position: {}",
-        recieved_successor.position, config.local.position
-    );
+}
 
-    config.successor = Some(recieved_successor.clone());
-    config.precessor = Some(received_network_information.longest_range.holder.clone());
+#[put("/network/leave")]
+async fn put_network_leave(
+    _authorized: Authorized,
+    _same_network: SameNetwork,
+    node_config: &State<Arc<RwLock<NodeConfig>>>,
+) -> Result<String, Custom<String>> {
+    let (successor, precessor, local, entries) = {
+        let mut config = node_config.write().expect("RWLock is poisoned");
 
-    let precessor = config
-        .precessor
-        .as_ref()
-        .expect("Precessor was just set, but does not exist");
-    match http_connect::write_json_to_node(
-        http_connect::WriteOperations::Put,
+        if !config.connected {
+            let error_message =
+                String::from("Node is not in a network and therefore can't leave the network.");
+            println!("{}", &error_message);
+            return Err(status::Custom(Status::FailedDependency, error_message));
+        }
+
+        if config.is_crashed() {
+            return Err(status::Custom(
+                Status::ServiceUnavailable,
+                String::from("Node is crashed"),
+            ));
+        }
+
+        // Alone in the ring there is nobody to hand our keys to
+        if config
+            .precessor
+            .as_ref()
+            .is_some_and(|precessor| is_local_node(&config, precessor))
+        {
+            return Ok(forget_network(&mut config));
+        }
+
+        let entries: Vec<KeyValuePair> = config
+            .storage
+            .scan()
+            .into_iter()
+            .map(|(key, entry)| KeyValuePair { key, entry })
+            .collect();
+
+        (
+            config
+                .successor
+                .clone()
+                .expect("Leaving network, but had no successor!"),
+            config
+                .precessor
+                .clone()
+                .expect("Leaving network, but had no precessor!"),
+            config.local.clone(),
+            entries,
+        )
+    };
+
+    // Update current state of our precessor by issuing get for its local
+    let precessor: Node = match http_connect::get_from_node(
         &precessor.hostname,
         precessor.port,
-        "ring/successor",
-        &config.local,
-    ) {
-        Ok(response) => response,
+        "ring/local",
+    )
+    .await
+    {
         Err(_err) => {
             return Err(status::Custom(
                 Status::FailedDependency,
-                String::from("Could not set successor of precessor"),
+                String::from("Could not get current state of precessor"),
             ))
         }
+        Ok(response) => match response.json::<Node>() {
+            Err(_err) => {
+                return Err(status::Custom(
+                    Status::FailedDependency,
+                    String::from("Could not parse JSON current state of precessor"),
+                ))
+            }
+            Ok(node) => node,
+        },
     };
 
-    let successor = config
-        .successor
-        .as_ref()
-        .expect("Successor was just set, but does not exist");
-    match http_connect::write_json_to_node(
-        http_connect::WriteOperations::Put,
+    // Update current state of our successor by issuing get for its local
+    let successor: Node = match http_connect::get_from_node(
         &successor.hostname,
         successor.port,
-        "ring/precessor",
-        &config.local,
-    ) {
-        Ok(response) => response,
+        "ring/local",
+    )
+    .await
+    {
         Err(_err) => {
             return Err(status::Custom(
                 Status::FailedDependency,
-                String::from("Could not set precessor of successor"),
+                String::from("Could not get current state of successor"),
             ))
         }
-    };
-
-    return Ok(format!(
-        "Joined network with ID: {}",
-        config
-            .network
-            .clone()
-            .map_or(String::from("Unknown"), |network| network
-                .network_id
-                .clone())
-    ));
-}
-
-#[put("/network/leave")]
-fn put_network_leave(
-    node_config: &State<Arc<RwLock<NodeConfig>>>,
-) -> Result<String, Custom<String>> {
-    let mut config = node_config.write().expect("RWLock is poisoned");
-
-    if !config.connected {
-        let error_message =
-            String::from("Node is not in a network and therefore can't leave the network.");
-        println!("{}", &error_message);
-        return Err(status::Custom(Status::FailedDependency, error_message));
-    }
-
-    if config.is_crashed() {
-        return Err(status::Custom(
-            Status::ServiceUnavailable,
-            String::from("Node is crashed"),
-        ));
-    }
-
-    let successor = config
-        .successor
-        .as_ref()
-        .expect("Leaving network, but had no successor!");
-    let precessor = config
-        .precessor
-        .as_ref()
-        .expect("Leaving network, but had no precessor!");
-
-    // Update current state of our precessor by issuing get for its local
-    let precessor: Node =
-        match http_connect::get_from_node(&precessor.hostname, precessor.port, "ring/local") {
+        Ok(response) => match response.json::<Node>() {
             Err(_err) => {
                 return Err(status::Custom(
                     Status::FailedDependency,
-                    String::from("Could not get current state of precessor"),
+                    String::from("Could not parse JSON current state of successor"),
                 ))
             }
-            Ok(response) => match response.json::<Node>() {
-                Err(_err) => {
-                    return Err(status::Custom(
-                        Status::FailedDependency,
-                        String::from("Could not parse JSON current state of precessor"),
-                    ))
-                }
-                Ok(node) => node,
-            },
-        };
+            Ok(node) => node,
+        },
+    };
 
-    // Update current state of our successor by issuing get for its local
-    let successor: Node =
-        match http_connect::get_from_node(&successor.hostname, successor.port, "ring/local") {
+    // Our range is inherited by the precessor, so every key we hold has to reach it before we leave.
+    // This happens before any pointers are changed, so a failed handoff leaves the ring untouched.
+    let is_only_node = precessor.hostname == local.hostname && precessor.port == local.port;
+    if !is_only_node {
+        match http_connect::write_json_to_node(
+            http_connect::WriteOperations::Post,
+            &precessor.hostname,
+            precessor.port,
+            "storage/handoff/accept",
+            &entries,
+        )
+        .await
+        {
+            Ok(_s) => println!("Handed off {} keys to precessor", entries.len()),
             Err(_err) => {
                 return Err(status::Custom(
                     Status::FailedDependency,
-                    String::from("Could not get current state of successor"),
+                    String::from("Could not hand off stored keys to precessor"),
                 ))
             }
-            Ok(response) => match response.json::<Node>() {
-                Err(_err) => {
-                    return Err(status::Custom(
-                        Status::FailedDependency,
-                        String::from("Could not parse JSON current state of successor"),
-                    ))
-                }
-                Ok(node) => node,
-            },
         };
+    }
 
     // Put our current precessor as precessor for our current successor
     match http_connect::write_json_to_node(
@@ -1064,7 +3110,9 @@ fn put_network_leave(
         successor.port,
         "ring/precessor",
         precessor.clone(),
-    ) {
+    )
+    .await
+    {
         Ok(_s) => _s,
         Err(_err) => {
             return Err(status::Custom(
@@ -1081,7 +3129,9 @@ fn put_network_leave(
         precessor.port,
         "ring/successor",
         successor,
-    ) {
+    )
+    .await
+    {
         Ok(_s) => _s,
         Err(_err) => {
             return Err(status::Custom(
@@ -1091,6 +3141,74 @@ fn put_network_leave(
         }
     };
 
+    // Writes accepted while we were handing off and switching pointers are not in the first copy. The
+    // ring now goes around us, so whatever changed since is sent after it.
+    let mut handed_off: HashMap<String, u64> = entries
+        .into_iter()
+        .map(|pair| (pair.key, pair.entry.version))
+        .collect();
+    if !is_only_node {
+        let late_entries: Vec<KeyValuePair> = node_config
+            .read()
+            .expect("RWLock is poisoned")
+            .storage
+            .scan()
+            .into_iter()
+            .filter(|(key, entry)| handed_off.get(key) != Some(&entry.version))
+            .map(|(key, entry)| KeyValuePair { key, entry })
+            .collect();
+
+        if !late_entries.is_empty() {
+            if let Err(_err) = http_connect::write_json_to_node(
+                http_connect::WriteOperations::Post,
+                &precessor.hostname,
+                precessor.port,
+                "storage/handoff/accept",
+                &late_entries,
+            )
+            .await
+            {
+                return Err(status::Custom(
+                    Status::FailedDependency,
+                    String::from("Could not hand off keys written while leaving to precessor"),
+                ));
+            }
+            println!(
+                "Handed off {} keys written while leaving to precessor",
+                late_entries.len()
+            );
+            handed_off.extend(
+                late_entries
+                    .into_iter()
+                    .map(|pair| (pair.key, pair.entry.version)),
+            );
+        }
+    }
+
+    let mut config = node_config.write().expect("RWLock is poisoned");
+
+    // The precessor acknowledged our keys, so local copies would only go stale. A key written again
+    // since it was handed off is kept.
+    if !is_only_node {
+        for (key, version) in handed_off {
+            let unchanged = config
+                .storage
+                .get(&key)
+                .is_some_and(|entry| entry.version == version);
+            if !unchanged {
+                continue;
+            }
+            if let Err(err) = config.storage.delete(&key) {
+                println!("Could not remove handed off key {}. Error: {}", key, err);
+            }
+        }
+    }
+
+    return Ok(forget_network(&mut config));
+}
+
+// Resets the ring state after leaving, and makes the other virtual nodes of the process leave as well
+fn forget_network(config: &mut NodeConfig) -> String {
     let network_id = config
         .network
         .as_ref()
@@ -1102,75 +3220,312 @@ fn put_network_leave(
     config.network = None;
     config.successor = None;
     config.precessor = None;
+    config.successor_list.clear();
     config.finger_table.clear();
-    config.local.position = 0;
-    config.local.position = RING_SIZE;
+    config.finger_table_size = 0;
+    config.neighbour_health.clear();
+    config.local.position = Identifier::zero();
+    config.local.range = Identifier::whole_ring();
+
+    virtual_nodes::send_to_siblings(
+        &config.virtual_nodes,
+        http_connect::WriteOperations::Put,
+        "network/leave",
+        String::new(),
+    );
+
+    format!("Left network {}", network_id)
+}
+
+fn main() {
+    let hostname = env::var("A1_HOSTNAME").expect("Hostname not provided!");
+    let port: u16 = env::var("A1_PORT")
+        .expect("Port not provided.")
+        .parse()
+        .expect("Unable to parse port value.");
+
+    // Has to be set before any position is created, as all of them wrap around at this width
+    if let Ok(bits) = env::var("A1_RING_BITS") {
+        identifier::set_ring_bits(bits.parse().expect("Unable to parse ring bits value."))
+            .expect("Invalid ring bits value.");
+    }
+
+    // Virtual nodes listen on the ports following A1_PORT
+    let virtual_node_count: u16 = env::var("A1_VIRTUAL_NODES").map_or(1, |count| {
+        count.parse().expect("Unable to parse virtual nodes value.")
+    });
+    if virtual_node_count == 0 {
+        panic!("A process must host at least one virtual node.");
+    }
+    let siblings = virtual_nodes::siblings(&hostname, port, virtual_node_count);
+
+    // Shared by all virtual nodes, as they send their requests through the same client
+    let milliseconds_from_env = |variable: &str, default: u64| -> Duration {
+        Duration::from_millis(env::var(variable).map_or(default, |milliseconds| {
+            milliseconds
+                .parse()
+                .expect("Unable to parse timeout value.")
+        }))
+    };
+    http_connect::set_settings(http_connect::ClientSettings {
+        connect_timeout: milliseconds_from_env(
+            "A1_CONNECT_TIMEOUT_MS",
+            http_connect::DEFAULT_CONNECT_TIMEOUT_MS,
+        ),
+        request: http_connect::RequestSettings {
+            timeout: milliseconds_from_env(
+                "A1_REQUEST_TIMEOUT_MS",
+                http_connect::DEFAULT_REQUEST_TIMEOUT_MS,
+            ),
+            retries: env::var("A1_REQUEST_RETRIES").map_or(
+                http_connect::DEFAULT_RETRIES,
+                |retries| {
+                    retries
+                        .parse()
+                        .expect("Unable to parse request retries value.")
+                },
+            ),
+            backoff: milliseconds_from_env(
+                "A1_RETRY_BACKOFF_MS",
+                http_connect::DEFAULT_RETRY_BACKOFF_MS,
+            ),
+            retry_timeouts: true,
+        },
+        pool: connection_pool::PoolSettings {
+            max_idle: env::var("A1_POOL_MAX_IDLE").map_or(
+                connection_pool::DEFAULT_POOL_MAX_IDLE,
+                |max_idle| {
+                    max_idle
+                        .parse()
+                        .expect("Unable to parse pool max idle value.")
+                },
+            ),
+            idle_timeout: milliseconds_from_env(
+                "A1_POOL_IDLE_TIMEOUT_MS",
+                connection_pool::DEFAULT_POOL_IDLE_TIMEOUT_MS,
+            ),
+            max_peers: env::var("A1_POOL_MAX_PEERS").map_or(
+                connection_pool::DEFAULT_POOL_MAX_PEERS,
+                |max_peers| {
+                    max_peers
+                        .parse()
+                        .expect("Unable to parse pool max peers value.")
+                },
+            ),
+        },
+        transport: env::var("A1_TRANSPORT").map_or(rpc::Transport::default(), |transport| {
+            transport.parse().expect("Unable to parse transport value.")
+        }),
+        rpc_port_offset: env::var("A1_RPC_PORT_OFFSET").map_or(
+            rpc::DEFAULT_RPC_PORT_OFFSET,
+            |offset| {
+                offset
+                    .parse()
+                    .expect("Unable to parse RPC port offset value.")
+            },
+        ),
+    })
+    .expect("Client settings have already been set.");
 
-    Ok(format!("Left network {}", network_id))
+    if let Ok(token) = env::var("A1_NETWORK_TOKEN") {
+        auth::set_token(token).expect("Invalid network token.");
+    }
+
+    // Either all three files are given, or the nodes speak plain HTTP
+    let tls_files = (
+        env::var("A1_TLS_CERT"),
+        env::var("A1_TLS_KEY"),
+        env::var("A1_TLS_CA"),
+    );
+    match tls_files {
+        (Ok(certificate), Ok(key), Ok(ca)) => {
+            if http_connect::settings().transport == rpc::Transport::Binary {
+                panic!("The binary transport does not support TLS.");
+            }
+            let context = tls::load(tls::TlsSettings {
+                certificate: PathBuf::from(certificate),
+                key: PathBuf::from(key),
+                ca: PathBuf::from(ca),
+            })
+            .expect("Unable to load TLS files.");
+            tls::set_context(context).expect("TLS has already been set up.");
+        }
+        (Err(_), Err(_), Err(_)) => {}
+        _ => panic!("A1_TLS_CERT, A1_TLS_KEY and A1_TLS_CA must be given together."),
+    }
+
+    rocket::execute(async move {
+        for sibling in siblings.iter() {
+            let (hostname, sibling_port) = (sibling.hostname.clone(), sibling.port);
+            rocket::tokio::spawn(launch(build_rocket(hostname, sibling_port, port, vec![])));
+        }
+
+        // The process, and with it every virtual node, ends when the first node shuts down
+        launch(build_rocket(hostname, port, port, siblings)).await;
+    });
+}
+
+async fn launch(rocket: rocket::Rocket<rocket::Build>) {
+    if let Err(err) = rocket.launch().await {
+        println!("Node stopped with an error: {}", err);
+    }
 }
 
-#[launch]
-fn rocket() -> _ {
+fn build_rocket(
+    hostname: String,
+    port: u16,
+    process: u16,
+    virtual_nodes: Vec<SuppliedNode>,
+) -> rocket::Rocket<rocket::Build> {
+    // Each node gets its own subdirectory, as several may share a filesystem
+    let data_directory = env::var("A1_DATA_DIR")
+        .ok()
+        .map(|directory| PathBuf::from(directory).join(format!("{}-{}", hostname, port)));
+
+    // Persistent storage is used by default whenever a data directory is given
+    let engine_kind: EngineKind = match env::var("A1_STORAGE_ENGINE") {
+        Ok(engine) => engine
+            .parse()
+            .expect("Unable to parse storage engine value."),
+        Err(_err) if data_directory.is_some() => EngineKind::Log,
+        Err(_err) => EngineKind::Memory,
+    };
+
+    let lookup_mode: LookupMode = env::var("A1_LOOKUP_MODE")
+        .map_or(LookupMode::default(), |mode| {
+            mode.parse().expect("Unable to parse lookup mode value.")
+        });
+
+    let storage = storage::open_engine(EngineSettings {
+        kind: engine_kind,
+        data_directory,
+        snapshot_interval: env::var("A1_SNAPSHOT_INTERVAL").ok().map(|interval| {
+            interval
+                .parse()
+                .expect("Unable to parse snapshot interval value.")
+        }),
+        capacity: env::var("A1_STORAGE_CAPACITY").ok().map(|capacity| {
+            capacity
+                .parse()
+                .expect("Unable to parse storage capacity value.")
+        }),
+    })
+    .expect("Unable to open storage engine.");
+
     let node_config = Arc::new(RwLock::new(NodeConfig {
         local: Node {
-            hostname: env::var("A1_HOSTNAME").expect("Hostname not provided!"),
-            port: env::var("A1_PORT")
-                .expect("Port not provided.")
-                .parse()
-                .expect("Unable to parse port value."),
-            position: 0,
-            range: 0,
+            hostname,
+            port,
+            process,
+            position: Identifier::zero(),
+            range: Identifier::whole_ring(),
         },
         successor: None,
         precessor: None,
+        successor_list: vec![],
         finger_table: vec![],
-        storage: Storage::new(),
+        finger_table_size: 0,
+        finger_table_mode: FingerTableMode::EvenlySpaced,
+        lookup_mode,
+        storage,
+        neighbour_health: HashMap::new(),
         network: None,
         connected: false,
         crashed: false,
+        join_strategy: JoinStrategy::default(),
+        virtual_nodes,
+        rejected_calls: RejectedCalls::default(),
+        metrics: NodeMetrics::default(),
     }));
 
-    node_config
-        .write()
-        .unwrap()
-        .storage
-        .store("key", "stored_value");
+    // An interval of 0 disables the task
+    let interval_from_env = |variable: &str, default: u64| -> Option<Duration> {
+        let milliseconds = env::var(variable).map_or(default, |interval| {
+            interval.parse().expect("Unable to parse interval value.")
+        });
+        (milliseconds > 0).then(|| Duration::from_millis(milliseconds))
+    };
+    let stabilization_settings = StabilizationSettings {
+        stabilize_interval: interval_from_env(
+            "A1_STABILIZE_INTERVAL_MS",
+            DEFAULT_STABILIZE_INTERVAL_MS,
+        ),
+        fix_fingers_interval: interval_from_env(
+            "A1_FIX_FINGERS_INTERVAL_MS",
+            DEFAULT_FIX_FINGERS_INTERVAL_MS,
+        ),
+        tombstone_ttl: interval_from_env("A1_TOMBSTONE_TTL_MS", DEFAULT_TOMBSTONE_TTL_MS),
+    };
 
-    println!(
-        "Retrieved: {}",
-        node_config
-            .read()
-            .unwrap()
-            .storage
-            .retrieve("key")
-            .expect("No value retrieved!")
-    );
+    let failure_detector_settings = FailureDetectorSettings {
+        heartbeat_interval: interval_from_env(
+            "A1_HEARTBEAT_INTERVAL_MS",
+            DEFAULT_HEARTBEAT_INTERVAL_MS,
+        ),
+        suspect_after: env::var("A1_SUSPECT_AFTER").map_or(DEFAULT_SUSPECT_AFTER, |count| {
+            count.parse().expect("Unable to parse suspect after value.")
+        }),
+        dead_after: env::var("A1_DEAD_AFTER").map_or(DEFAULT_DEAD_AFTER, |count| {
+            count.parse().expect("Unable to parse dead after value.")
+        }),
+    };
 
-    rocket::build().manage(node_config).mount(
-        "/",
-        routes![
-            helloworld,
-            shutdown,
-            post_sim_crash,
-            post_sim_recover,
-            get_storage,
-            put_storage,
-            get_network,
-            get_node_info,
-            get_precessor,
-            get_successor,
-            get_local,
-            put_precessor,
-            put_successor,
-            put_local,
-            get_finger_table,
-            calculate_finger_table,
-            get_network_request_join,
-            get_network_longest_range,
-            post_network_longest_range,
-            put_network_initialize,
-            put_network_join,
-            put_network_leave
-        ],
-    )
+    // The port is set here rather than through ROCKET_PORT, as every virtual node needs its own
+    let figment = tls::configure_rocket(rocket::Config::figment().merge(("port", port)));
+    rocket::custom(figment)
+        .manage(node_config)
+        .attach(stabilization::fairing(stabilization_settings))
+        .attach(failure_detector::fairing(failure_detector_settings))
+        .attach(rpc::fairing())
+        .attach(metrics::request_start_fairing())
+        .attach(metrics::request_metrics_fairing())
+        .register("/", catchers![membership::misdirected])
+        .mount(
+            "/",
+            // Requests the handlers send to other nodes carry the network ID of this node
+            membership::send_as_node(routes![
+                helloworld,
+                shutdown,
+                post_sim_crash,
+                post_sim_recover,
+                get_storage,
+                put_storage,
+                get_storage_replica,
+                put_storage_replica,
+                delete_storage_replica,
+                delete_storage,
+                post_storage_handoff,
+                post_storage_handoff_release,
+                post_storage_handoff_accept,
+                get_network,
+                get_node_info,
+                get_connection_stats,
+                get_rejected_calls,
+                get_metrics,
+                get_precessor,
+                get_successor,
+                get_successor_list,
+                calculate_successor_list,
+                get_neighbour_status,
+                get_local,
+                put_precessor,
+                put_successor,
+                put_notify,
+                put_local,
+                get_load,
+                get_finger_table,
+                calculate_finger_table,
+                get_find_successor,
+                get_next_hop,
+                get_lookup,
+                get_network_request_join,
+                get_network_description,
+                get_network_longest_range,
+                post_network_longest_range,
+                put_network_initialize,
+                put_network_join,
+                post_network_rebalance,
+                put_network_leave
+            ]),
+        )
 }
@@ -0,0 +1,225 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::route::{self, Handler, Route};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio;
+use rocket::Data;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+use crate::failure_detector::node_id;
+use crate::node_config::NodeConfig;
+
+pub const NETWORK_HEADER: &str = "X-Network-Id";
+pub const SENDER_HEADER: &str = "X-Sender";
+
+tokio::task_local! {
+    // Node on whose behalf requests to other nodes are sent
+    static SENDER: Arc<RwLock<NodeConfig>>;
+}
+
+// Calls from nodes of other networks turned away, by the network they came from
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "rocket::serde")]
+pub struct RejectedCalls {
+    pub total: u64,
+    pub by_network: HashMap<String, u64>,
+}
+
+// Runs the future on behalf of the node, so every request it sends carries the network ID and
+// hostname:port of that node
+pub async fn as_node<F>(node_config: Arc<RwLock<NodeConfig>>, future: F) -> F::Output
+where
+    F: Future,
+{
+    return SENDER.scope(node_config, future).await;
+}
+
+// Node the current task sends requests for, to be handed on to tasks it spawns
+pub fn current_node() -> Option<Arc<RwLock<NodeConfig>>> {
+    return SENDER.try_with(|node_config| node_config.clone()).ok();
+}
+
+// Network ID and identity of the sending node. Nodes outside any network send only their identity.
+pub fn sender_headers() -> Vec<(&'static str, String)> {
+    let Some(node_config) = current_node() else {
+        return vec![];
+    };
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    let mut headers = vec![(SENDER_HEADER, node_id(&config.local))];
+    if let Some(network) = config.network.as_ref() {
+        headers.push((NETWORK_HEADER, network.network_id.clone()));
+    }
+    return headers;
+}
+
+// Wraps every route so the handler sends its requests on behalf of the node it runs on
+pub fn send_as_node(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(SendAsNode {
+                inner: route.handler,
+            });
+            route
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+struct SendAsNode {
+    inner: Box<dyn Handler>,
+}
+
+#[rocket::async_trait]
+impl Handler for SendAsNode {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        match request.rocket().state::<Arc<RwLock<NodeConfig>>>() {
+            Some(node_config) => {
+                return as_node(node_config.clone(), self.inner.handle(request, data)).await
+            }
+            None => return self.inner.handle(request, data).await,
+        }
+    }
+}
+
+// Proof that the request did not come from a node of another network. Requests without a network
+// ID, from clients or from nodes that are still joining, pass, as do all requests while we are not
+// in a network ourselves.
+pub struct SameNetwork(());
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SameNetwork {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        let Some(node_config) = request.rocket().state::<Arc<RwLock<NodeConfig>>>() else {
+            return Outcome::Success(SameNetwork(()));
+        };
+
+        let headers = request.headers();
+        match check(
+            node_config,
+            headers.get_one(NETWORK_HEADER),
+            headers.get_one(SENDER_HEADER),
+        ) {
+            Ok(same_network) => Outcome::Success(same_network),
+            Err(err) => {
+                request.local_cache(|| Rejection(err.clone()));
+                Outcome::Error((Status::MisdirectedRequest, err))
+            }
+        }
+    }
+}
+
+// Proof that the request came from a node of our network, for routes that change the ring. Unlike
+// SameNetwork, once we are in a network the sender has to give its network ID and identity.
+pub struct RingMember(());
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RingMember {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, String> {
+        let Some(node_config) = request.rocket().state::<Arc<RwLock<NodeConfig>>>() else {
+            return Outcome::Success(RingMember(()));
+        };
+
+        let headers = request.headers();
+        match check_member(
+            node_config,
+            headers.get_one(NETWORK_HEADER),
+            headers.get_one(SENDER_HEADER),
+        ) {
+            Ok(ring_member) => Outcome::Success(ring_member),
+            Err(err) => {
+                request.local_cache(|| Rejection(err.clone()));
+                Outcome::Error((Status::MisdirectedRequest, err))
+            }
+        }
+    }
+}
+
+// Like check, but turns away calls without a network ID or sender while we are in a network. Those
+// count toward the total of rejected calls only.
+pub fn check_member(
+    node_config: &RwLock<NodeConfig>,
+    network_id: Option<&str>,
+    sender: Option<&str>,
+) -> Result<RingMember, String> {
+    if let (Some(network_id), Some(_sender)) = (network_id, sender) {
+        check(node_config, Some(network_id), sender)?;
+        return Ok(RingMember(()));
+    }
+
+    let mut config = node_config.write().expect("RWLock is poisoned");
+    let Some(local_network_id) = config
+        .network
+        .as_ref()
+        .map(|network| network.network_id.clone())
+    else {
+        return Ok(RingMember(()));
+    };
+    config.rejected_calls.total += 1;
+
+    let error_message = format!(
+        "Rejected call from {} without a network ID or sender, this node is in network {}",
+        sender.unwrap_or("unknown node"),
+        local_network_id
+    );
+    println!("{}", &error_message);
+    return Err(error_message);
+}
+
+// Compares the network ID the sender gave with our own, counting the call if they differ
+pub fn check(
+    node_config: &RwLock<NodeConfig>,
+    network_id: Option<&str>,
+    sender: Option<&str>,
+) -> Result<SameNetwork, String> {
+    let Some(network_id) = network_id else {
+        return Ok(SameNetwork(()));
+    };
+
+    let local_network_id = match node_config
+        .read()
+        .expect("RWLock is poisoned")
+        .network
+        .as_ref()
+    {
+        None => return Ok(SameNetwork(())),
+        Some(network) if network.network_id == network_id => return Ok(SameNetwork(())),
+        Some(network) => network.network_id.clone(),
+    };
+
+    let mut config = node_config.write().expect("RWLock is poisoned");
+    config.rejected_calls.total += 1;
+    *config
+        .rejected_calls
+        .by_network
+        .entry(String::from(network_id))
+        .or_default() += 1;
+
+    let error_message = format!(
+        "Rejected call from {} of network {}, this node is in network {}",
+        sender.unwrap_or("unknown node"),
+        network_id,
+        local_network_id
+    );
+    println!("{}", &error_message);
+    return Err(error_message);
+}
+
+// Reason a call was rejected, kept for the catcher to answer with
+struct Rejection(String);
+
+// Body of the answer to a call from another network, naming both networks
+#[catch(421)]
+pub fn misdirected(request: &Request) -> String {
+    return request
+        .local_cache(|| Rejection(String::from("Rejected call from a node of another network")))
+        .0
+        .clone();
+}
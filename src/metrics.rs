@@ -0,0 +1,350 @@
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::Request;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+use crate::http_connect::NodeConnectionError;
+use crate::node_config::NodeConfig;
+
+// Upper bounds of the latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0,
+];
+const HOP_BUCKETS: [f64; 8] = [1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 16.0, 64.0];
+
+// Failed requests to other nodes, by kind of error. The client is shared by all virtual nodes of the
+// process, so these are counted for the whole process, after all retries.
+static CONNECTION_FAILURES: [AtomicU64; 6] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+const CONNECTION_FAILURE_KINDS: [&str; 6] = [
+    "timeout",
+    "refused",
+    "broken",
+    "bad_status",
+    "decode",
+    "other_network",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StorageOperation {
+    Get,
+    Put,
+    Delete,
+}
+
+impl StorageOperation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StorageOperation::Get => "get",
+            StorageOperation::Put => "put",
+            StorageOperation::Delete => "delete",
+        }
+    }
+}
+
+struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>, // Observations up to each bucket bound, not cumulative
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Histogram {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(index) = self.buckets.iter().position(|bound| value <= *bound) {
+            self.counts[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    // Labels are given without braces, and may be empty
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in self.buckets.iter().zip(self.counts.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        );
+        let _ = writeln!(out, "{}_sum{} {}", name, braced(labels), self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braced(labels), self.count);
+    }
+}
+
+struct RouteMetrics {
+    statuses: BTreeMap<u16, u64>,
+    latency: Histogram,
+}
+
+struct Recorded {
+    routes: BTreeMap<(String, String), RouteMetrics>, // By method and route
+    storage_operations: BTreeMap<(StorageOperation, bool), u64>, // By operation and whether it was forwarded
+    lookup_hops: Histogram,
+}
+
+// Counters of a single node. Recorded under a read guard of NodeConfig, so they bring their own lock.
+pub struct NodeMetrics {
+    recorded: Mutex<Recorded>,
+}
+
+impl Default for NodeMetrics {
+    fn default() -> Self {
+        NodeMetrics {
+            recorded: Mutex::new(Recorded {
+                routes: BTreeMap::new(),
+                storage_operations: BTreeMap::new(),
+                lookup_hops: Histogram::new(&HOP_BUCKETS),
+            }),
+        }
+    }
+}
+
+impl NodeMetrics {
+    pub fn record_request(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        let mut recorded = self.recorded.lock().expect("Mutex is poisoned");
+        let route_metrics = recorded
+            .routes
+            .entry((String::from(method), String::from(route)))
+            .or_insert_with(|| RouteMetrics {
+                statuses: BTreeMap::new(),
+                latency: Histogram::new(&LATENCY_BUCKETS),
+            });
+        *route_metrics.statuses.entry(status).or_default() += 1;
+        route_metrics.latency.observe(seconds);
+    }
+
+    // Storage request answered as owner, or passed on towards the owner
+    pub fn record_storage_operation(&self, operation: StorageOperation, forwarded: bool) {
+        let mut recorded = self.recorded.lock().expect("Mutex is poisoned");
+        *recorded
+            .storage_operations
+            .entry((operation, forwarded))
+            .or_default() += 1;
+    }
+
+    // Nodes a request went through on its way to the owner, for requests that started at this node
+    pub fn record_lookup_hops(&self, hops: usize) {
+        let mut recorded = self.recorded.lock().expect("Mutex is poisoned");
+        recorded.lookup_hops.observe(hops as f64);
+    }
+}
+
+pub fn record_connection_failure(err: &NodeConnectionError) {
+    if let Some(index) = CONNECTION_FAILURE_KINDS
+        .iter()
+        .position(|kind| *kind == err.kind())
+    {
+        CONNECTION_FAILURES[index].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Fairings timing every request from the moment it arrives until its response is ready
+pub fn request_start_fairing() -> AdHoc {
+    AdHoc::on_request("Request start", |request, _data| {
+        Box::pin(async move {
+            request.local_cache(|| RequestStart(Instant::now()));
+        })
+    })
+}
+
+pub fn request_metrics_fairing() -> AdHoc {
+    AdHoc::on_response("Request metrics", |request, response| {
+        Box::pin(async move {
+            record_response(request, response.status());
+        })
+    })
+}
+
+struct RequestStart(Instant);
+
+fn record_response(request: &Request<'_>, status: Status) {
+    let Some(node_config) = request.rocket().state::<Arc<RwLock<NodeConfig>>>() else {
+        return;
+    };
+    let started = request.local_cache(|| RequestStart(Instant::now())).0;
+
+    // Labelled by route rather than path, so every key does not get its own series
+    let route = request.route().map_or(String::from("unmatched"), |route| {
+        String::from(route.uri.origin.path().as_str())
+    });
+    node_config
+        .read()
+        .expect("RWLock is poisoned")
+        .metrics
+        .record_request(
+            request.method().as_str(),
+            &route,
+            status.code,
+            started.elapsed().as_secs_f64(),
+        );
+}
+
+// All metrics of the node in the Prometheus text format
+pub fn render(config: &NodeConfig) -> String {
+    let mut out = String::new();
+    let recorded = config.metrics.recorded.lock().expect("Mutex is poisoned");
+
+    header(
+        &mut out,
+        "chord_requests_total",
+        "counter",
+        "Requests answered, by route and status.",
+    );
+    for ((method, route), route_metrics) in recorded.routes.iter() {
+        for (status, count) in route_metrics.statuses.iter() {
+            let _ = writeln!(
+                out,
+                "chord_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method, route, status, count
+            );
+        }
+    }
+
+    header(
+        &mut out,
+        "chord_request_duration_seconds",
+        "histogram",
+        "Time from receiving a request until its response was ready, by route.",
+    );
+    for ((method, route), route_metrics) in recorded.routes.iter() {
+        let labels = format!("method=\"{}\",route=\"{}\"", method, route);
+        route_metrics
+            .latency
+            .render(&mut out, "chord_request_duration_seconds", &labels);
+    }
+
+    header(
+        &mut out,
+        "chord_storage_operations_total",
+        "counter",
+        "Storage requests answered as owner of the key, or forwarded towards the owner.",
+    );
+    for ((operation, forwarded), count) in recorded.storage_operations.iter() {
+        let handled = if *forwarded { "forwarded" } else { "local" };
+        let _ = writeln!(
+            out,
+            "chord_storage_operations_total{{operation=\"{}\",handled=\"{}\"}} {}",
+            operation.as_str(),
+            handled,
+            count
+        );
+    }
+
+    header(
+        &mut out,
+        "chord_lookup_hops",
+        "histogram",
+        "Nodes a storage request or lookup went through to reach the owner, for those started at this node.",
+    );
+    recorded
+        .lookup_hops
+        .render(&mut out, "chord_lookup_hops", "");
+
+    gauge(
+        &mut out,
+        "chord_storage_keys",
+        "Keys held, as owner or replica, including tombstones of deleted keys.",
+        config.storage.len() as f64,
+    );
+    gauge(
+        &mut out,
+        "chord_storage_bytes",
+        "Size of the keys and values held.",
+        config.storage.bytes() as f64,
+    );
+    gauge(
+        &mut out,
+        "chord_node_position_ratio",
+        "Position of the node, as a share of the ring.",
+        config.local.position.as_ring_share(),
+    );
+    gauge(
+        &mut out,
+        "chord_node_range_ratio",
+        "Range the node owns, as a share of the ring.",
+        config.local.range.range_length().as_ring_share(),
+    );
+    gauge(
+        &mut out,
+        "chord_finger_table_size",
+        "Entries in the finger table.",
+        config.finger_table.len() as f64,
+    );
+    gauge(
+        &mut out,
+        "chord_successor_list_size",
+        "Entries in the successor list.",
+        config.successor_list.len() as f64,
+    );
+    gauge(
+        &mut out,
+        "chord_connected",
+        "Whether the node is in a network.",
+        if config.connected { 1.0 } else { 0.0 },
+    );
+
+    header(
+        &mut out,
+        "chord_connection_failures_total",
+        "counter",
+        "Requests to other nodes that failed after all retries, by kind, for the whole process.",
+    );
+    for (kind, failures) in CONNECTION_FAILURE_KINDS
+        .iter()
+        .zip(CONNECTION_FAILURES.iter())
+    {
+        let _ = writeln!(
+            out,
+            "chord_connection_failures_total{{kind=\"{}\"}} {}",
+            kind,
+            failures.load(Ordering::Relaxed)
+        );
+    }
+
+    return out;
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    header(out, name, "gauge", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn braced(labels: &str) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    return format!("{{{}}}", labels);
+}
@@ -1,4 +1,10 @@
-use crate::{Network, Node, Storage};  
+use crate::failure_detector::NeighbourHealth;
+use crate::lookup::LookupMode;
+use crate::membership::RejectedCalls;
+use crate::metrics::NodeMetrics;
+use crate::storage::StorageEngine;
+use crate::{FingerTableMode, JoinStrategy, Network, Node, SuppliedNode};
+use std::collections::HashMap;
 
 pub struct NodeConfig {
     pub network: Option<Network>,
@@ -6,9 +12,18 @@ pub struct NodeConfig {
     pub local: Node,
     pub successor: Option<Node>,
     pub precessor: Option<Node>,
+    pub successor_list: Vec<Node>, // The next replication_factor - 1 nodes after us, holding our replicas
     pub finger_table: Vec<Node>,
-    pub storage: Storage,
+    pub finger_table_size: usize, // Size requested by the last finger table calculation, 0 if never calculated
+    pub finger_table_mode: FingerTableMode,
+    pub lookup_mode: LookupMode, // Used for storage requests that do not ask for a lookup mode themselves
+    pub storage: Box<dyn StorageEngine>,
+    pub neighbour_health: HashMap<String, NeighbourHealth>, // Heartbeat state of our neighbours, keyed by hostname:port
     pub crashed: bool,
+    pub join_strategy: JoinStrategy, // How we last joined, and rejoin after a crash
+    pub virtual_nodes: Vec<SuppliedNode>, // The other virtual nodes of this process, only known by the first one
+    pub rejected_calls: RejectedCalls,    // Calls from nodes of other networks
+    pub metrics: NodeMetrics,
 }
 
 impl NodeConfig {
@@ -26,4 +41,11 @@ impl NodeConfig {
     pub fn is_crashed(&self) -> bool {
         self.crashed
     }
+
+    // Replication factor of the network we are in, or 1 when not connected
+    pub fn replication_factor(&self) -> usize {
+        self.network
+            .as_ref()
+            .map_or(1, |network| network.replication_factor)
+    }
 }
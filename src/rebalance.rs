@@ -0,0 +1,262 @@
+use rocket::serde::{Deserialize, Serialize};
+
+use crate::failure_detector::node_id;
+use crate::identifier::Identifier;
+use crate::stabilization::is_same_node;
+use crate::virtual_nodes::is_same_process;
+use crate::{
+    http_connect, is_location_in_range, key_to_location, write_to_replicas, KeyRange, KeyValuePair,
+    Node,
+};
+
+// A node along with the number of keys in its range
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct NodeLoad {
+    pub node: Node,
+    pub keys: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "rocket::serde")]
+pub struct RebalanceReport {
+    pub before: Vec<NodeLoad>,
+    pub after: Vec<NodeLoad>,
+    pub moved_keys: usize,
+}
+
+// Moves every node to evenly spaced positions, keeping their order on the ring and the position of
+// the first node, then writes every key to its new replica set and removes it from the nodes that left it. Not safe against concurrent joins and
+// leaves, and writes arriving while keys are moved may be lost, so it is meant to run on a quiet network.
+pub async fn rebalance(
+    local: &Node,
+    successor: &Node,
+    replication_factor: usize,
+) -> Result<RebalanceReport, String> {
+    let ring = walk_ring(local, successor).await?;
+    let before = get_loads(&ring).await?;
+
+    if before.len() < 2 {
+        return Ok(RebalanceReport {
+            after: before.clone(),
+            before,
+            moved_keys: 0,
+        });
+    }
+
+    // Each node keeps its successor and precessor, only positions and ranges change
+    let step = Identifier::ring_fraction(before.len() as u64);
+    let first_position = before[0].node.position;
+    let mut target: Vec<Node> = vec![];
+    let mut position = first_position;
+    for load in before.iter() {
+        let mut node = load.node.clone();
+        node.position = position;
+        node.range = step;
+        target.push(node);
+        position = position + step;
+    }
+
+    // The last node covers whatever rounding left over, up to the first node
+    let last = target.len() - 1;
+    target[last].range = first_position - target[last].position;
+
+    // Copy every key before any position changes, each node is asked only for the keys in its own range
+    let mut owned_keys: Vec<(Node, Vec<KeyValuePair>)> = vec![];
+    for load in before.iter() {
+        owned_keys.push((load.node.clone(), get_keys_in_range(&load.node).await?));
+    }
+
+    for (index, node) in target.iter().enumerate() {
+        let successor = &target[(index + 1) % target.len()];
+        let precessor = &target[(index + target.len() - 1) % target.len()];
+        put_node(node, "ring/local", node).await?;
+        put_node(node, "ring/successor", successor).await?;
+        put_node(node, "ring/precessor", precessor).await?;
+        println!("Moved {} to position {}", node_id(node), node.position);
+    }
+
+    // Successor lists hold the old positions, and replicas are chosen from them
+    if replication_factor > 1 {
+        for node in target.iter() {
+            if let Err(_err) = http_connect::write_body_to_node(
+                http_connect::WriteOperations::Put,
+                &node.hostname,
+                node.port,
+                "ring/calculate_successor_list",
+                "text/plain",
+                "",
+            )
+            .await
+            {
+                println!("Could not recalculate successor list of {}", node_id(node));
+            }
+        }
+    }
+
+    // Nodes keep their order, so a node has the same index before and after and the replicas of a key
+    // are the owner and the nodes following it in either list, passing over virtual nodes of a process
+    // already in the set
+    let replica_set = |owner_index: usize| -> Vec<Node> {
+        let mut replicas: Vec<Node> = vec![];
+        for offset in 0..target.len() {
+            let node = &target[(owner_index + offset) % target.len()];
+            if replicas.len() < replication_factor
+                && !replicas
+                    .iter()
+                    .any(|replica| is_same_process(replica, node))
+            {
+                replicas.push(node.clone());
+            }
+        }
+        replicas
+    };
+
+    let mut moved_keys = 0;
+    for (old_index, (old_owner, entries)) in owned_keys.into_iter().enumerate() {
+        let old_replicas = replica_set(old_index);
+
+        for pair in entries {
+            let location = key_to_location(&pair.key);
+            let new_index = target
+                .iter()
+                .position(|node| is_location_in_range(location, node.position, node.range))
+                .expect("Target positions cover the whole ring");
+
+            // Every key is written to its whole new replica set, as a node may now follow a different owner
+            let replicas = replica_set(new_index);
+            if write_to_replicas(&replicas[..1], &pair.key, &pair.entry).await == 0 {
+                println!(
+                    "Could not move key {} to {}, it stays on {}",
+                    pair.key,
+                    node_id(&replicas[0]),
+                    node_id(&old_owner)
+                );
+                continue;
+            }
+            write_to_replicas(&replicas[1..], &pair.key, &pair.entry).await;
+
+            // Nodes that were replicas of the key, but are not anymore, would keep a copy that goes stale
+            for old_replica in old_replicas.iter() {
+                if replicas.iter().any(|node| is_same_node(node, old_replica)) {
+                    continue;
+                }
+                if let Err(_err) = http_connect::write_body_to_node(
+                    http_connect::WriteOperations::Delete,
+                    &old_replica.hostname,
+                    old_replica.port,
+                    &format!("storage/{}/replica", pair.key),
+                    "text/plain",
+                    "",
+                )
+                .await
+                {
+                    println!(
+                        "Could not remove moved key {} from {}",
+                        pair.key,
+                        node_id(old_replica)
+                    );
+                }
+            }
+
+            if new_index != old_index {
+                moved_keys += 1;
+            }
+        }
+    }
+
+    let after = get_loads(&target).await?;
+
+    return Ok(RebalanceReport {
+        before,
+        after,
+        moved_keys,
+    });
+}
+
+// All nodes in ring order, starting with the local node
+async fn walk_ring(local: &Node, successor: &Node) -> Result<Vec<Node>, String> {
+    let mut ring = vec![local.clone()];
+    let mut current = successor.clone();
+
+    while !is_same_node(&current, local) {
+        // Coming back to a node other than the local one means the successors are broken
+        if ring.iter().any(|visited| is_same_node(visited, &current)) {
+            return Err(format!(
+                "Successors loop back to {} without reaching the local node",
+                node_id(&current)
+            ));
+        }
+        ring.push(current.clone());
+
+        current =
+            match http_connect::get_from_node(&current.hostname, current.port, "ring/successor")
+                .await
+            {
+                Err(_err) => {
+                    return Err(format!("Could not get successor of {}", node_id(&current)))
+                }
+                Ok(response) => response
+                    .json::<Node>()
+                    .map_err(|_err| String::from("Unable to parse successor from JSON."))?,
+            };
+    }
+
+    return Ok(ring);
+}
+
+async fn get_loads(nodes: &[Node]) -> Result<Vec<NodeLoad>, String> {
+    let mut loads = vec![];
+
+    for node in nodes.iter() {
+        match http_connect::get_from_node(&node.hostname, node.port, "ring/load").await {
+            Err(_err) => return Err(format!("Could not get load of {}", node_id(node))),
+            Ok(response) => loads.push(
+                response
+                    .json::<NodeLoad>()
+                    .map_err(|_err| String::from("Unable to parse node load from JSON."))?,
+            ),
+        }
+    }
+
+    return Ok(loads);
+}
+
+async fn get_keys_in_range(node: &Node) -> Result<Vec<KeyValuePair>, String> {
+    let key_range = KeyRange {
+        position: node.position,
+        range: node.range,
+    };
+
+    match http_connect::write_json_to_node(
+        http_connect::WriteOperations::Post,
+        &node.hostname,
+        node.port,
+        "storage/handoff",
+        &key_range,
+    )
+    .await
+    {
+        Err(_err) => return Err(format!("Could not get keys of {}", node_id(node))),
+        Ok(response) => {
+            return response
+                .json::<Vec<KeyValuePair>>()
+                .map_err(|_err| String::from("Unable to parse keys from JSON."))
+        }
+    }
+}
+
+async fn put_node(target: &Node, path: &str, node: &Node) -> Result<(), String> {
+    match http_connect::write_json_to_node(
+        http_connect::WriteOperations::Put,
+        &target.hostname,
+        target.port,
+        path,
+        node,
+    )
+    .await
+    {
+        Err(_err) => return Err(format!("Could not update {} of {}", path, node_id(target))),
+        Ok(_response) => return Ok(()),
+    }
+}
@@ -0,0 +1,833 @@
+use http::Method;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::{self, time};
+use rocket::State;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::auth::{self, Authorized};
+use crate::http_connect::{self, NodeConnectionError};
+use crate::lookup::LookupMode;
+use crate::lookup_trace::{self, TraceRequest};
+use crate::membership::{self, RingMember, SameNetwork};
+use crate::node_config::NodeConfig;
+use crate::storage::Entry;
+use crate::{Consistency, LongestRangeRequest, Node, ReplicatedResponse};
+
+pub const DEFAULT_RPC_PORT_OFFSET: u16 = 1000;
+
+const MAX_FRAME_LENGTH: usize = 64 * 1024 * 1024; // Larger answers are taken as a broken or foreign peer
+const MAX_HEAD_LENGTH: usize = 8 * 1024; // Call and headers of a request, keys included
+const MAX_BODY_LENGTH: usize = 1024 * 1024; // Same as the limit Rocket puts on JSON bodies
+
+// Connections to other nodes that are not in use, by hostname:port of their RPC listener
+type IdleConnections = HashMap<String, Vec<(TcpStream, Instant)>>;
+
+static IDLE_CONNECTIONS: OnceLock<Mutex<IdleConnections>> = OnceLock::new();
+
+// How requests to other nodes are sent. Requests without a binary form, and requests carrying extra
+// headers such as a trace, always go over HTTP.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Transport {
+    #[default]
+    Http,
+    Binary, // Length-prefixed MessagePack frames to the RPC port of the node
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(transport: &str) -> Result<Self, Self::Err> {
+        match transport {
+            "http" => Ok(Transport::Http),
+            "binary" => Ok(Transport::Binary),
+            other => Err(format!("Unknown transport: {}", other)),
+        }
+    }
+}
+
+// Requests that have a binary form, each standing for the HTTP route of the same name. The body
+// follows in a frame of its own: the value the route takes as JSON, encoded as MessagePack, or the
+// plain text value of a storage write.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "rocket::serde")]
+pub enum RpcCall {
+    Ping,
+    GetLocal,
+    GetSuccessor,
+    GetPrecessor,
+    PutSuccessor,
+    PutPrecessor,
+    Notify,
+    LongestRange,
+    GetStorage {
+        key: String,
+        consistency: Option<Consistency>,
+        lookup: Option<LookupMode>,
+    },
+    PutStorage {
+        key: String,
+        consistency: Option<Consistency>,
+        lookup: Option<LookupMode>,
+    },
+    DeleteStorage {
+        key: String,
+        lookup: Option<LookupMode>,
+    },
+    GetReplica {
+        key: String,
+    },
+    PutReplica {
+        key: String,
+    },
+    DeleteReplica {
+        key: String,
+    },
+}
+
+impl RpcCall {
+    // Calls standing for routes open to clients, which do not take the network token
+    fn is_open(&self) -> bool {
+        match self {
+            RpcCall::Ping
+            | RpcCall::GetStorage { .. }
+            | RpcCall::PutStorage { .. }
+            | RpcCall::DeleteStorage { .. } => return true,
+            _ => return false,
+        }
+    }
+
+    // Calls standing for routes that change the ring or the replicas it holds, which only nodes of our
+    // network may make
+    fn changes_ring(&self) -> bool {
+        match self {
+            RpcCall::PutSuccessor
+            | RpcCall::PutPrecessor
+            | RpcCall::Notify
+            | RpcCall::PutReplica { .. }
+            | RpcCall::DeleteReplica { .. } => return true,
+            _ => return false,
+        }
+    }
+
+    // Method and route of the HTTP route the call stands for, to count it along with that route
+    pub fn route(&self) -> (&'static str, &'static str) {
+        match self {
+            RpcCall::Ping => return ("GET", "/helloworld"),
+            RpcCall::GetLocal => return ("GET", "/ring/local"),
+            RpcCall::GetSuccessor => return ("GET", "/ring/successor"),
+            RpcCall::GetPrecessor => return ("GET", "/ring/precessor"),
+            RpcCall::PutSuccessor => return ("PUT", "/ring/successor"),
+            RpcCall::PutPrecessor => return ("PUT", "/ring/precessor"),
+            RpcCall::Notify => return ("PUT", "/ring/notify"),
+            RpcCall::LongestRange => return ("POST", "/network/longest_range"),
+            RpcCall::GetStorage { .. } => return ("GET", "/storage/<key>"),
+            RpcCall::PutStorage { .. } => return ("PUT", "/storage/<key>"),
+            RpcCall::DeleteStorage { .. } => return ("DELETE", "/storage/<key>"),
+            RpcCall::GetReplica { .. } => return ("GET", "/storage/<key>/replica"),
+            RpcCall::PutReplica { .. } => return ("PUT", "/storage/<key>/replica"),
+            RpcCall::DeleteReplica { .. } => return ("DELETE", "/storage/<key>/replica"),
+        }
+    }
+}
+
+// Head of a request, the call along with the headers the HTTP request would carry: the network
+// token, and the network ID and identity of the sender
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RpcRequest {
+    headers: Vec<(String, String)>,
+    call: RpcCall,
+}
+
+impl RpcRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _value)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_name, value)| value.as_str())
+    }
+}
+
+// Status and headers of an answer, sent ahead of its body like in HTTP
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct RpcHead {
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+struct RpcAnswer {
+    head: RpcHead,
+    body: Vec<u8>,
+}
+
+// The binary form of an HTTP request, if it has one
+pub fn call_for(method: &Method, path: &str) -> Option<RpcCall> {
+    let (route, query) = path.split_once('?').unwrap_or((path, ""));
+    let parameters: HashMap<&str, &str> = query
+        .split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .collect();
+    let consistency = match parameters.get("consistency") {
+        Some(consistency) => Some(consistency.parse().ok()?),
+        None => None,
+    };
+    let lookup = match parameters.get("lookup") {
+        Some(lookup) => Some(lookup.parse().ok()?),
+        None => None,
+    };
+
+    let call = match (method.as_str(), route) {
+        ("GET", "helloworld") => RpcCall::Ping,
+        ("GET", "ring/local") => RpcCall::GetLocal,
+        ("GET", "ring/successor") => RpcCall::GetSuccessor,
+        ("GET", "ring/precessor") => RpcCall::GetPrecessor,
+        ("PUT", "ring/successor") => RpcCall::PutSuccessor,
+        ("PUT", "ring/precessor") => RpcCall::PutPrecessor,
+        ("PUT", "ring/notify") => RpcCall::Notify,
+        ("POST", "network/longest_range") => RpcCall::LongestRange,
+        (method, route) => {
+            let key = route.strip_prefix("storage/")?;
+            let (key, replica) = match key.strip_suffix("/replica") {
+                Some(key) => (String::from(key), true),
+                None => (String::from(key), false),
+            };
+            if key.contains('/') {
+                return None;
+            }
+
+            match (method, replica) {
+                ("GET", false) => RpcCall::GetStorage {
+                    key,
+                    consistency,
+                    lookup,
+                },
+                ("PUT", false) => RpcCall::PutStorage {
+                    key,
+                    consistency,
+                    lookup,
+                },
+                ("DELETE", false) => RpcCall::DeleteStorage { key, lookup },
+                ("GET", true) => RpcCall::GetReplica { key },
+                ("PUT", true) => RpcCall::PutReplica { key },
+                ("DELETE", true) => RpcCall::DeleteReplica { key },
+                _ => return None,
+            }
+        }
+    };
+
+    return Some(call);
+}
+
+// Sends a single request to the RPC listener of a node, returning the status, headers and body of
+// its answer. The connection is kept for the next request once the answer has been read.
+pub async fn send(
+    hostname: &str,
+    rpc_port: u16,
+    call: &RpcCall,
+    body: &[u8],
+    timeout: Duration,
+) -> Result<(u16, HashMap<String, String>, Vec<u8>), NodeConnectionError> {
+    let peer_id = format!("{}:{}", hostname, rpc_port);
+    let mut headers: Vec<(String, String)> = membership::sender_headers()
+        .into_iter()
+        .map(|(name, value)| (String::from(name), value))
+        .collect();
+    if let Some(bearer) = auth::bearer() {
+        headers.push((String::from(auth::AUTHORIZATION_HEADER), bearer));
+    }
+    let request = RpcRequest {
+        headers,
+        call: call.clone(),
+    };
+    let head = rmp_serde::to_vec(&request).expect("Could not serialize call.");
+
+    let attempt = async {
+        // The peer may have closed an idle connection just as we took it. If nothing of the answer
+        // arrived, the call is sent once more on a fresh connection, even when it is not idempotent.
+        let mut answer = None;
+        if let Some(mut stream) = take_idle_connection(&peer_id) {
+            match exchange(&mut stream, &head, body).await {
+                Err(ExchangeError::BeforeAnswer(_err)) => {}
+                Err(ExchangeError::DuringAnswer(err)) => return Err(err),
+                Ok(received) => answer = Some((stream, received)),
+            }
+        }
+        let (stream, (answer_head, answer_body)) = match answer {
+            Some(answer) => answer,
+            None => {
+                let mut stream = connect(hostname, rpc_port).await?;
+                match exchange(&mut stream, &head, body).await {
+                    Err(ExchangeError::BeforeAnswer(err) | ExchangeError::DuringAnswer(err)) => {
+                        return Err(err)
+                    }
+                    Ok(received) => (stream, received),
+                }
+            }
+        };
+
+        return_idle_connection(peer_id.clone(), stream);
+
+        let headers = answer_head
+            .headers
+            .into_iter()
+            .map(|(name, value)| (name.to_lowercase(), value))
+            .collect();
+        return Ok((answer_head.status, headers, answer_body));
+    };
+
+    match time::timeout(timeout, attempt).await {
+        Err(_elapsed) => return Err(NodeConnectionError::Timeout),
+        Ok(result) => return result,
+    }
+}
+
+// Where an exchange on a connection failed
+enum ExchangeError {
+    BeforeAnswer(NodeConnectionError), // Nothing of the answer had arrived
+    DuringAnswer(NodeConnectionError),
+}
+
+// Sends a request on the connection and reads its answer
+async fn exchange(
+    stream: &mut TcpStream,
+    head: &[u8],
+    body: &[u8],
+) -> Result<(RpcHead, Vec<u8>), ExchangeError> {
+    write_frame(stream, head)
+        .await
+        .map_err(ExchangeError::BeforeAnswer)?;
+    write_frame(stream, body)
+        .await
+        .map_err(ExchangeError::BeforeAnswer)?;
+
+    // A connection closed by the peer shows up here, as the end of the stream or a reset
+    match stream.peek(&mut [0u8; 1]).await {
+        Ok(0) => {
+            return Err(ExchangeError::BeforeAnswer(NodeConnectionError::Broken(
+                String::from("connection closed by peer"),
+            )))
+        }
+        Err(err) => {
+            return Err(ExchangeError::BeforeAnswer(NodeConnectionError::Broken(
+                err.to_string(),
+            )))
+        }
+        Ok(_length) => {}
+    }
+
+    let answer_head = read_frame(stream, MAX_FRAME_LENGTH)
+        .await
+        .and_then(|frame| {
+            rmp_serde::from_slice::<RpcHead>(&frame)
+                .map_err(|err| NodeConnectionError::Decode(err.to_string()))
+        })
+        .map_err(ExchangeError::DuringAnswer)?;
+    let answer_body = read_frame(stream, MAX_FRAME_LENGTH)
+        .await
+        .map_err(ExchangeError::DuringAnswer)?;
+    return Ok((answer_head, answer_body));
+}
+
+async fn connect(hostname: &str, rpc_port: u16) -> Result<TcpStream, NodeConnectionError> {
+    let connect_timeout = http_connect::settings().connect_timeout;
+
+    match time::timeout(connect_timeout, TcpStream::connect((hostname, rpc_port))).await {
+        Err(_elapsed) => return Err(NodeConnectionError::Timeout),
+        Ok(Err(_err)) => return Err(NodeConnectionError::Refused),
+        Ok(Ok(stream)) => {
+            // Frames are small and a request waits for its answer, so they should not be held back
+            let _ = stream.set_nodelay(true);
+            return Ok(stream);
+        }
+    }
+}
+
+fn idle_connections() -> &'static Mutex<IdleConnections> {
+    IDLE_CONNECTIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Connections idle for longer than the pool allows are dropped rather than reused
+fn take_idle_connection(peer_id: &str) -> Option<TcpStream> {
+    let idle_timeout = http_connect::settings().pool.idle_timeout;
+    let mut idle = idle_connections().lock().expect("Mutex is poisoned");
+    let streams = idle.get_mut(peer_id)?;
+
+    while let Some((stream, since)) = streams.pop() {
+        if since.elapsed() < idle_timeout {
+            return Some(stream);
+        }
+    }
+    return None;
+}
+
+fn return_idle_connection(peer_id: String, stream: TcpStream) {
+    let max_idle = http_connect::settings().pool.max_idle;
+    let mut idle = idle_connections().lock().expect("Mutex is poisoned");
+    let streams = idle.entry(peer_id).or_default();
+
+    if streams.len() < max_idle {
+        streams.push((stream, Instant::now()));
+    }
+}
+
+// Frames are a 4 byte big-endian length followed by that many bytes
+async fn write_frame(stream: &mut TcpStream, payload: &[u8]) -> Result<(), NodeConnectionError> {
+    let length = (payload.len() as u32).to_be_bytes();
+
+    stream
+        .write_all(&length)
+        .await
+        .map_err(|err| NodeConnectionError::Broken(err.to_string()))?;
+    stream
+        .write_all(payload)
+        .await
+        .map_err(|err| NodeConnectionError::Broken(err.to_string()))
+}
+
+async fn read_frame(
+    stream: &mut TcpStream,
+    max_length: usize,
+) -> Result<Vec<u8>, NodeConnectionError> {
+    let mut length = [0u8; 4];
+    stream
+        .read_exact(&mut length)
+        .await
+        .map_err(|err| NodeConnectionError::Broken(err.to_string()))?;
+
+    let length = u32::from_be_bytes(length) as usize;
+    if length > max_length {
+        return Err(NodeConnectionError::Decode(format!(
+            "Frame of {} bytes is too large",
+            length
+        )));
+    }
+
+    let mut payload = vec![0u8; length];
+    stream
+        .read_exact(&mut payload)
+        .await
+        .map_err(|err| NodeConnectionError::Broken(err.to_string()))?;
+    return Ok(payload);
+}
+
+// Fairing starting the RPC listener once Rocket is listening, on the HTTP port plus the RPC port offset
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Binary RPC", |rocket| {
+        Box::pin(async move {
+            let settings = http_connect::settings();
+            if settings.transport != Transport::Binary {
+                return;
+            }
+
+            let node_config = rocket
+                .state::<Arc<RwLock<NodeConfig>>>()
+                .expect("NodeConfig is not managed")
+                .clone();
+
+            let Some(rpc_port) = rocket.config().port.checked_add(settings.rpc_port_offset) else {
+                println!(
+                    "No RPC port after {}, not listening for RPC",
+                    rocket.config().port
+                );
+                return;
+            };
+
+            match TcpListener::bind((rocket.config().address, rpc_port)).await {
+                Err(err) => println!("Could not listen for RPC on port {}: {}", rpc_port, err),
+                Ok(listener) => {
+                    println!("Listening for RPC on port {}", rpc_port);
+                    tokio::spawn(serve(listener, node_config));
+                }
+            }
+        })
+    })
+}
+
+async fn serve(listener: TcpListener, node_config: Arc<RwLock<NodeConfig>>) {
+    loop {
+        match listener.accept().await {
+            Err(err) => println!("Could not accept RPC connection: {}", err),
+            Ok((stream, _address)) => {
+                let _ = stream.set_nodelay(true);
+                tokio::spawn(serve_connection(stream, node_config.clone()));
+            }
+        }
+    }
+}
+
+// Answers the requests on a connection one after the other, until the other node closes it or leaves
+// it idle for longer than the pool keeps connections. The head of a request is checked before its body
+// is read, and a rejected request closes the connection, as its body is never read.
+async fn serve_connection(mut stream: TcpStream, node_config: Arc<RwLock<NodeConfig>>) {
+    let settings = http_connect::settings();
+
+    loop {
+        let head = match time::timeout(
+            settings.pool.idle_timeout,
+            read_frame(&mut stream, MAX_HEAD_LENGTH),
+        )
+        .await
+        {
+            Ok(Ok(head)) => head,
+            Err(_) | Ok(Err(_)) => return,
+        };
+        let started = Instant::now();
+
+        let request = match rmp_serde::from_slice::<RpcRequest>(&head) {
+            Ok(request) => request,
+            Err(err) => {
+                let answer = error_answer(Status::BadRequest, format!("Unknown call: {}", err));
+                let _ = write_answer(&mut stream, &answer).await;
+                return;
+            }
+        };
+        let (method, route) = request.call.route();
+
+        let (answer, keep_open) = match admit(&node_config, &request) {
+            Err(answer) => (answer, false),
+            Ok(admission) => {
+                match time::timeout(
+                    settings.request.timeout,
+                    read_frame(&mut stream, MAX_BODY_LENGTH),
+                )
+                .await
+                {
+                    Err(_) | Ok(Err(_)) => return,
+                    Ok(Ok(body)) => {
+                        let answer = dispatch(&node_config, admission, request.call, body);
+                        (membership::as_node(node_config.clone(), answer).await, true)
+                    }
+                }
+            }
+        };
+
+        node_config
+            .read()
+            .expect("RWLock is poisoned")
+            .metrics
+            .record_request(
+                method,
+                route,
+                answer.head.status,
+                started.elapsed().as_secs_f64(),
+            );
+
+        if write_answer(&mut stream, &answer).await.is_err() || !keep_open {
+            return;
+        }
+    }
+}
+
+async fn write_answer(
+    stream: &mut TcpStream,
+    answer: &RpcAnswer,
+) -> Result<(), NodeConnectionError> {
+    let answer_head = rmp_serde::to_vec(&answer.head).expect("Could not serialize answer.");
+    write_frame(stream, &answer_head).await?;
+    return write_frame(stream, &answer.body).await;
+}
+
+// What the head of a call showed about its sender
+struct Admission {
+    same_network: SameNetwork,
+    ring_member: Option<RingMember>, // Only checked for calls that change the ring
+    authorized: Result<Authorized, String>,
+}
+
+// Checks the network of the sender, and the token for calls that need it, from the head alone. Calls
+// that change the ring also need the sender to name its network.
+fn admit(node_config: &RwLock<NodeConfig>, request: &RpcRequest) -> Result<Admission, RpcAnswer> {
+    let network_id = request.header(membership::NETWORK_HEADER);
+    let sender = request.header(membership::SENDER_HEADER);
+
+    let same_network = membership::check(node_config, network_id, sender)
+        .map_err(|err| error_answer(Status::MisdirectedRequest, err))?;
+
+    let ring_member = match request.call.changes_ring() {
+        false => None,
+        true => Some(
+            membership::check_member(node_config, network_id, sender)
+                .map_err(|err| error_answer(Status::MisdirectedRequest, err))?,
+        ),
+    };
+
+    let authorized = auth::check(request.header(auth::AUTHORIZATION_HEADER));
+    if let (false, Err(err)) = (request.call.is_open(), authorized.as_ref()) {
+        println!("Rejected RPC call {:?}: {}", request.call, err);
+        return Err(error_answer(Status::Unauthorized, err.clone()));
+    }
+
+    return Ok(Admission {
+        same_network,
+        ring_member,
+        authorized,
+    });
+}
+
+// Hands the call to the route handler it stands for, so both transports behave the same
+async fn dispatch(
+    node_config: &Arc<RwLock<NodeConfig>>,
+    admission: Admission,
+    call: RpcCall,
+    body: Vec<u8>,
+) -> RpcAnswer {
+    let state: &State<Arc<RwLock<NodeConfig>>> = State::from(node_config);
+    let Admission {
+        same_network,
+        ring_member,
+        authorized,
+    } = admission;
+
+    match call {
+        RpcCall::Ping => return text_answer(crate::helloworld(state)),
+        RpcCall::GetStorage {
+            key,
+            consistency,
+            lookup,
+        } => {
+            let result = crate::get_storage(
+                same_network,
+                state,
+                &key,
+                consistency,
+                lookup,
+                TraceRequest(false),
+            )
+            .await;
+            return replicated_answer(result);
+        }
+        RpcCall::PutStorage {
+            key,
+            consistency,
+            lookup,
+        } => match std::str::from_utf8(&body) {
+            Err(_err) => {
+                return error_answer(Status::BadRequest, String::from("Value is not UTF-8"))
+            }
+            Ok(value) => {
+                let result = crate::put_storage(
+                    same_network,
+                    state,
+                    &key,
+                    value,
+                    consistency,
+                    lookup,
+                    TraceRequest(false),
+                )
+                .await;
+                return replicated_answer(result);
+            }
+        },
+        RpcCall::DeleteStorage { key, lookup } => {
+            return text_answer(crate::delete_storage(same_network, state, &key, lookup).await)
+        }
+        // Everything else stands for a ring-internal route, which takes the network token
+        call => match (authorized, ring_member) {
+            (Err(err), _) => return error_answer(Status::Unauthorized, err),
+            (Ok(authorized), Some(ring_member)) => {
+                return dispatch_ring_change(state, authorized, ring_member, call, body)
+            }
+            (Ok(authorized), None) => {
+                return dispatch_authorized(state, authorized, same_network, call, body).await
+            }
+        },
+    }
+}
+
+async fn dispatch_authorized(
+    state: &State<Arc<RwLock<NodeConfig>>>,
+    authorized: Authorized,
+    same_network: SameNetwork,
+    call: RpcCall,
+    body: Vec<u8>,
+) -> RpcAnswer {
+    match call {
+        RpcCall::GetLocal => {
+            return value_answer(crate::get_local(authorized, same_network, state))
+        }
+        RpcCall::GetSuccessor => {
+            return value_answer(crate::get_successor(authorized, same_network, state))
+        }
+        RpcCall::GetPrecessor => {
+            return value_answer(crate::get_precessor(authorized, same_network, state))
+        }
+        RpcCall::LongestRange => match decode::<LongestRangeRequest>(&body) {
+            Err(answer) => return answer,
+            Ok(request) => {
+                let result = crate::post_network_longest_range(
+                    authorized,
+                    same_network,
+                    state,
+                    Json(request),
+                )
+                .await;
+                return value_answer(result);
+            }
+        },
+        RpcCall::GetReplica { key } => {
+            return value_answer(crate::get_storage_replica(
+                authorized,
+                same_network,
+                state,
+                &key,
+            ))
+        }
+        RpcCall::Ping
+        | RpcCall::GetStorage { .. }
+        | RpcCall::PutStorage { .. }
+        | RpcCall::DeleteStorage { .. } => {
+            unreachable!("Client calls are dispatched without the network token")
+        }
+        RpcCall::PutSuccessor
+        | RpcCall::PutPrecessor
+        | RpcCall::Notify
+        | RpcCall::PutReplica { .. }
+        | RpcCall::DeleteReplica { .. } => {
+            unreachable!("Calls that change the ring are dispatched with the sender checked")
+        }
+    }
+}
+
+fn dispatch_ring_change(
+    state: &State<Arc<RwLock<NodeConfig>>>,
+    authorized: Authorized,
+    ring_member: RingMember,
+    call: RpcCall,
+    body: Vec<u8>,
+) -> RpcAnswer {
+    match call {
+        RpcCall::PutSuccessor => match decode::<Node>(&body) {
+            Err(answer) => return answer,
+            Ok(node) => {
+                return empty_answer(crate::put_successor(
+                    authorized,
+                    ring_member,
+                    state,
+                    Json(node),
+                ))
+            }
+        },
+        RpcCall::PutPrecessor => match decode::<Node>(&body) {
+            Err(answer) => return answer,
+            Ok(node) => {
+                return empty_answer(crate::put_precessor(
+                    authorized,
+                    ring_member,
+                    state,
+                    Json(node),
+                ))
+            }
+        },
+        RpcCall::Notify => match decode::<Node>(&body) {
+            Err(answer) => return answer,
+            Ok(node) => {
+                return empty_answer(crate::put_notify(
+                    authorized,
+                    ring_member,
+                    state,
+                    Json(node),
+                ))
+            }
+        },
+        RpcCall::PutReplica { key } => match decode::<Entry>(&body) {
+            Err(answer) => return answer,
+            Ok(entry) => {
+                return empty_answer(crate::put_storage_replica(
+                    authorized,
+                    ring_member,
+                    state,
+                    &key,
+                    Json(entry),
+                ))
+            }
+        },
+        RpcCall::DeleteReplica { key } => {
+            return text_answer(crate::delete_storage_replica(
+                authorized,
+                ring_member,
+                state,
+                &key,
+            ))
+        }
+        _ => unreachable!("Only calls that change the ring are admitted as ring members"),
+    }
+}
+
+fn decode<T>(body: &[u8]) -> Result<T, RpcAnswer>
+where
+    T: rocket::serde::de::DeserializeOwned,
+{
+    rmp_serde::from_slice(body).map_err(|err| {
+        error_answer(
+            Status::BadRequest,
+            format!("Unable to parse body from MessagePack: {}", err),
+        )
+    })
+}
+
+fn answer(status: Status, headers: Vec<(String, String)>, body: Vec<u8>) -> RpcAnswer {
+    RpcAnswer {
+        head: RpcHead {
+            status: status.code,
+            headers,
+        },
+        body,
+    }
+}
+
+fn error_answer(status: Status, message: String) -> RpcAnswer {
+    return answer(status, vec![], message.into_bytes());
+}
+
+fn value_answer<T: Serialize>(result: Result<Json<T>, Custom<String>>) -> RpcAnswer {
+    match result {
+        Err(Custom(status, message)) => return error_answer(status, message),
+        Ok(Json(value)) => {
+            let body = rmp_serde::to_vec(&value).expect("Could not serialize answer.");
+            return answer(Status::Ok, vec![], body);
+        }
+    }
+}
+
+fn text_answer(result: Result<String, Custom<String>>) -> RpcAnswer {
+    match result {
+        Err(Custom(status, message)) => return error_answer(status, message),
+        Ok(text) => return answer(Status::Ok, vec![], text.into_bytes()),
+    }
+}
+
+fn empty_answer(result: Result<(), Custom<String>>) -> RpcAnswer {
+    match result {
+        Err(Custom(status, message)) => return error_answer(status, message),
+        Ok(()) => return answer(Status::Ok, vec![], vec![]),
+    }
+}
+
+fn replicated_answer(result: Result<ReplicatedResponse, Custom<String>>) -> RpcAnswer {
+    match result {
+        Err(Custom(status, message)) => return error_answer(status, message),
+        Ok(response) => {
+            let headers = vec![
+                (
+                    String::from(response.replicas_answered.name().as_str()),
+                    String::from(response.replicas_answered.value()),
+                ),
+                (
+                    String::from(lookup_trace::LOOKUP_HOPS_HEADER),
+                    response.lookup_hops.to_string(),
+                ),
+            ];
+            return answer(Status::Ok, headers, response.value.into_bytes());
+        }
+    }
+}
@@ -6,7 +6,7 @@ BINARY_FILE="inf2300-a1-bin-x86_64-unknown-linux-gnu"
 
 # See if an argument is provided
 if [ -z "$1" ]; then  # -z checks if the variable is empty
-    echo "No argument provided, please provide the number of servers to start: $0 <number_of_servers> [size of finger table (default 0)]"
+    echo "No argument provided, please provide the number of servers to start: $0 <number_of_servers> [size of finger table (default 0)] [replication factor (default 1)]"
     exit 1
 fi
 
@@ -27,6 +27,13 @@ else
 fi
 
 
+replication_factor=1
+# See if the argument is an integer
+if [[ "$3" =~ $regex_positive_integer ]] && [ "$3" -gt 0 ]; then
+    replication_factor=$3
+fi
+
+
 if [ -f $BINARY_FILE ]; then
    rm $BINARY_FILE
 fi
@@ -100,7 +107,7 @@ do
             if [ $deployed_nodes_count -eq 0 ]
             then
                 echo "Initializing network on first node."
-                curl -v -X "PUT" -H "Content-Type: application/json" --data "{\"network_id\": \"chord-network\"}" "http://$node:$port/network/initialize"
+                curl -v -X "PUT" -H "Content-Type: application/json" --data "{\"network_id\": \"chord-network\", \"replication_factor\": $replication_factor}" "http://$node:$port/network/initialize"
             else
                 echo "Joining node to previous node."
                 curl -v -X "PUT" -H "Content-Type: application/json" --data "{\"hostname\": \"$previous_node\", \"port\":$previous_port}" "http://$node:$port/network/join"
@@ -120,6 +127,13 @@ then
     done
 fi
 
+if [ $replication_factor -gt 1 ]
+then
+    for service in "${deployed_services[@]}"; do
+        curl -v -X "PUT" "http://$service/ring/calculate_successor_list"
+    done
+fi
+
 
 # Initialize the JSON string
 json_result="["
@@ -0,0 +1,216 @@
+use rocket::fairing::AdHoc;
+use rocket::tokio;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::membership;
+use crate::node_config::NodeConfig;
+use crate::storage;
+use crate::{
+    build_finger_table, build_successor_list, http_connect, is_location_between, set_successor,
+    Node,
+};
+
+pub struct StabilizationSettings {
+    pub stabilize_interval: Option<Duration>, // None disables the task
+    pub fix_fingers_interval: Option<Duration>,
+    pub tombstone_ttl: Option<Duration>, // Tombstones are purged once they are this old
+}
+
+// Fairing starting the Chord maintenance tasks once Rocket is listening
+pub fn fairing(settings: StabilizationSettings) -> AdHoc {
+    AdHoc::on_liftoff("Stabilization", move |rocket| {
+        Box::pin(async move {
+            let node_config = rocket
+                .state::<Arc<RwLock<NodeConfig>>>()
+                .expect("NodeConfig is not managed")
+                .clone();
+
+            if let Some(interval) = settings.stabilize_interval {
+                println!("Stabilizing every {:?}", interval);
+                spawn_periodic(node_config.clone(), interval, stabilize);
+            }
+
+            if let Some(interval) = settings.fix_fingers_interval {
+                println!("Fixing fingers every {:?}", interval);
+                spawn_periodic(node_config.clone(), interval, fix_fingers);
+            }
+
+            if let Some(ttl) = settings.tombstone_ttl {
+                println!("Purging tombstones older than {:?}", ttl);
+                spawn_periodic(node_config, ttl, move |node_config| {
+                    purge_tombstones(node_config, ttl)
+                });
+            }
+        })
+    })
+}
+
+// Runs the task every period for as long as the node runs, a run is finished before the next one starts
+pub fn spawn_periodic<F, R>(node_config: Arc<RwLock<NodeConfig>>, period: Duration, task: F)
+where
+    F: Fn(Arc<RwLock<NodeConfig>>) -> R + Send + Sync + 'static,
+    R: Future<Output = Result<(), String>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            // Run as a task of its own, so a panic ends only this run and not the whole schedule
+            let run = membership::as_node(node_config.clone(), task(node_config.clone()));
+            match tokio::spawn(run).await {
+                Ok(Ok(())) => {}
+                Ok(Err(error_message)) => println!("Maintenance task failed: {}", error_message),
+                Err(err) => println!("Maintenance task panicked: {}", err),
+            }
+        }
+    });
+}
+
+// Only connected and running nodes take part in maintenance.
+// Returns a copy of the local node and successor, so no lock is held during network calls.
+pub fn maintenance_snapshot(node_config: &Arc<RwLock<NodeConfig>>) -> Option<(Node, Node)> {
+    let config = node_config.read().expect("RWLock is poisoned");
+
+    if config.is_crashed() || !config.connected {
+        return None;
+    }
+
+    config
+        .successor
+        .clone()
+        .map(|successor| (config.local.clone(), successor))
+}
+
+pub fn is_same_node(a: &Node, b: &Node) -> bool {
+    a.hostname == b.hostname && a.port == b.port
+}
+
+// Chord stabilize: adopt our successor's precessor if it sits between us, then notify our successor about us
+async fn stabilize(node_config: Arc<RwLock<NodeConfig>>) -> Result<(), String> {
+    let (local, original_successor) = match maintenance_snapshot(&node_config) {
+        Some(snapshot) => snapshot,
+        None => return Ok(()),
+    };
+    let mut successor = original_successor.clone();
+
+    if is_same_node(&local, &successor) {
+        return Ok(());
+    }
+
+    let successor_precessor =
+        match http_connect::get_from_node(&successor.hostname, successor.port, "ring/precessor")
+            .await
+        {
+            Err(_err) => return Err(String::from("Could not get precessor of successor.")),
+            Ok(response) => response.json::<Node>().ok(),
+        };
+
+    if let Some(candidate) = successor_precessor {
+        if !is_same_node(&candidate, &local)
+            && is_location_between(candidate.position, local.position, successor.position)
+        {
+            println!(
+                "Stabilize found closer successor {}:{}",
+                candidate.hostname, candidate.port
+            );
+            successor = candidate;
+        }
+    }
+
+    // Also refresh the position and range of the successor, they change when nodes join or leave
+    if let Ok(response) =
+        http_connect::get_from_node(&successor.hostname, successor.port, "ring/local").await
+    {
+        if let Ok(current) = response.json::<Node>() {
+            successor = current;
+        }
+    }
+
+    if let Err(_err) = http_connect::write_json_to_node(
+        http_connect::WriteOperations::Put,
+        &successor.hostname,
+        successor.port,
+        "ring/notify",
+        &local,
+    )
+    .await
+    {
+        return Err(String::from("Could not notify successor."));
+    }
+
+    let wanted = node_config
+        .read()
+        .expect("RWLock is poisoned")
+        .replication_factor()
+        - 1;
+    let successor_list = build_successor_list(&local, successor.clone(), wanted).await?;
+
+    let mut config = node_config.write().expect("RWLock is poisoned");
+
+    // Skip the update if the ring was changed by a join or leave while we were asking around
+    let successor_changed = config
+        .successor
+        .as_ref()
+        .is_none_or(|current| !is_same_node(current, &original_successor));
+    if config.local.position != local.position || !config.connected || successor_changed {
+        return Ok(());
+    }
+
+    set_successor(&mut config, successor);
+    config.successor_list = successor_list;
+
+    Ok(())
+}
+
+// Rebuilds the finger table with the size and mode it was last calculated with
+async fn fix_fingers(node_config: Arc<RwLock<NodeConfig>>) -> Result<(), String> {
+    let (local, successor) = match maintenance_snapshot(&node_config) {
+        Some(snapshot) => snapshot,
+        None => return Ok(()),
+    };
+
+    let (size, mode) = {
+        let config = node_config.read().expect("RWLock is poisoned");
+        (config.finger_table_size, config.finger_table_mode)
+    };
+    if size == 0 {
+        return Ok(());
+    }
+
+    let finger_table = build_finger_table(&local, successor, size, mode)
+        .await
+        .map_err(|err| err.1)?;
+
+    let mut config = node_config.write().expect("RWLock is poisoned");
+    if config.local.position != local.position || !config.connected {
+        return Ok(());
+    }
+    config.finger_table = finger_table;
+
+    Ok(())
+}
+
+// Removes the tombstones of deleted keys, once replicas have had time to learn of the delete
+async fn purge_tombstones(
+    node_config: Arc<RwLock<NodeConfig>>,
+    ttl: Duration,
+) -> Result<(), String> {
+    let deleted_before = storage::now_in_milliseconds().saturating_sub(ttl.as_millis() as u64);
+
+    let purged = node_config
+        .read()
+        .expect("RWLock is poisoned")
+        .storage
+        .purge_tombstones(deleted_before)
+        .map_err(|err| format!("Could not purge tombstones. Error: {}", err))?;
+
+    if purged > 0 {
+        println!("Purged {} tombstones", purged);
+    }
+    Ok(())
+}
@@ -1,26 +1,207 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use rocket::serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub struct Storage {
-    storage: Arc<RwLock<HashMap<String, String>>>,
+mod log;
+mod lru;
+mod memory;
+
+pub use self::log::LogEngine;
+pub use self::lru::LruEngine;
+pub use self::memory::MemoryEngine;
+
+// A stored value together with its version, replicas resolve disagreements by keeping the highest version
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub struct Entry {
+    pub value: String,
+    pub version: u64,
+    // Set on the tombstone a delete leaves in place of the value, in milliseconds since the Unix epoch.
+    // The tombstone keeps older copies on other nodes from bringing the key back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<u64>,
 }
 
-impl Storage {
-    pub fn new() -> Self {
-        println!("Initialized HashMap storage!");
-        Storage {
-            storage: Arc::new(RwLock::new(HashMap::new())),
-        }
+impl Entry {
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    // The entry, unless it is a tombstone
+    pub fn live(entry: Option<Entry>) -> Option<Entry> {
+        entry.filter(|entry| !entry.is_deleted())
+    }
+
+    // Whether this entry wins over the other. Equal versions are settled by the value, so every node
+    // keeps the same one whichever order the writes arrive in.
+    pub fn supersedes(&self, other: &Entry) -> bool {
+        (self.version, &self.value) > (other.version, &other.value)
+    }
+}
+
+// What StorageEngine::update does with the stored entry of a key
+pub enum Change {
+    Keep,
+    Put(Entry),
+    Remove,
+}
+
+// Common interface for every key-value engine a node can store its keys in.
+// Engines handle their own locking, so all methods take &self and can be called under a read guard of NodeConfig.
+// Tombstones are stored like any other entry, get and scan return them as well.
+pub trait StorageEngine: Send + Sync {
+    fn get(&self, key: &str) -> Option<Entry>;
+
+    // Returns the removed entry, or None if the key was not stored
+    fn delete(&self, key: &str) -> io::Result<Option<Entry>>;
+
+    // Applies the change decided from the stored entry, in one step under the lock of the engine.
+    // Returns whether anything was written.
+    fn update(
+        &self,
+        key: &str,
+        change: &mut dyn FnMut(Option<&Entry>) -> Change,
+    ) -> io::Result<bool>;
+
+    // Stores the value with the version after the stored one, or version 1 for a new key. Done in one
+    // step, so concurrent writes to a key never get the same version.
+    fn put_next_version(&self, key: &str, value: String) -> io::Result<Entry> {
+        let mut written: Option<Entry> = None;
+        self.update(key, &mut |stored| {
+            let entry = Entry {
+                value: value.clone(),
+                version: stored.map_or(1, |entry| entry.version + 1),
+                deleted_at: None,
+            };
+            written = Some(entry.clone());
+            Change::Put(entry)
+        })?;
+        Ok(written.expect("Every update stores the next version"))
     }
 
-    pub fn store(&self, key: &str, value: &str) {
-        let mut storage = self.storage.write().expect("RWLock poisoned");
-        storage.insert(key.to_string(), value.to_string());
+    // Stores the entry unless the stored one supersedes or equals it. Returns whether it was stored.
+    fn put_if_newer(&self, key: &str, entry: Entry) -> io::Result<bool> {
+        self.update(
+            key,
+            &mut |stored| match stored.is_none_or(|stored| entry.supersedes(stored)) {
+                true => Change::Put(entry.clone()),
+                false => Change::Keep,
+            },
+        )
+    }
+
+    // Replaces the value with a tombstone of the next version. Returns the deleted entry, or None if the
+    // key was not stored or already deleted.
+    fn delete_next_version(&self, key: &str) -> io::Result<Option<Entry>> {
+        let mut deleted: Option<Entry> = None;
+        self.update(key, &mut |stored| match stored {
+            Some(stored) if !stored.is_deleted() => {
+                deleted = Some(stored.clone());
+                Change::Put(Entry {
+                    value: String::new(),
+                    version: stored.version + 1,
+                    deleted_at: Some(now_in_milliseconds()),
+                })
+            }
+            _ => Change::Keep,
+        })?;
+        Ok(deleted)
+    }
+
+    // Removes tombstones left before the given time. Returns how many were removed.
+    fn purge_tombstones(&self, deleted_before: u64) -> io::Result<usize> {
+        let mut purged = 0;
+        for (key, _entry) in self.scan() {
+            let removed = self.update(&key, &mut |stored| match stored {
+                Some(Entry {
+                    deleted_at: Some(deleted_at),
+                    ..
+                }) if *deleted_at < deleted_before => Change::Remove,
+                _ => Change::Keep,
+            })?;
+            if removed {
+                purged += 1;
+            }
+        }
+        Ok(purged)
     }
 
-    pub fn retrieve(&self, key: &str) -> Option<String> {
-        let storage = self.storage.read().expect("RWLock poisoned");
-        let value = storage.get(key).map_or(None, |v| Some(v.to_string()));
-        return value;
+    // All key-value pairs currently held by the engine, in no particular order
+    fn scan(&self) -> Vec<(String, Entry)>;
+
+    fn len(&self) -> usize;
+
+    // Size of all keys and values held, without versions or engine overhead. Kept up to date on every
+    // write, so it is cheap enough to read on every metrics scrape.
+    fn bytes(&self) -> usize;
+
+    // Short name used when logging which engine a node runs
+    fn name(&self) -> &'static str;
+}
+
+pub fn now_in_milliseconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+// What a key and its value add to StorageEngine::bytes
+fn entry_size(key: &str, entry: &Entry) -> usize {
+    key.len() + entry.value.len()
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum EngineKind {
+    Memory,
+    Log,
+    Lru,
+}
+
+impl FromStr for EngineKind {
+    type Err = String;
+
+    fn from_str(engine: &str) -> Result<Self, Self::Err> {
+        match engine {
+            "memory" => Ok(EngineKind::Memory),
+            "log" => Ok(EngineKind::Log),
+            "lru" => Ok(EngineKind::Lru),
+            other => Err(format!(
+                "Unknown storage engine '{}', expected one of: memory, log, lru",
+                other
+            )),
+        }
     }
 }
+
+pub struct EngineSettings {
+    pub kind: EngineKind,
+    pub data_directory: Option<PathBuf>,
+    pub snapshot_interval: Option<usize>,
+    pub capacity: Option<usize>,
+}
+
+pub fn open_engine(settings: EngineSettings) -> Result<Box<dyn StorageEngine>, String> {
+    let engine: Box<dyn StorageEngine> = match settings.kind {
+        EngineKind::Memory => Box::new(MemoryEngine::new()),
+        EngineKind::Log => {
+            let data_directory = settings
+                .data_directory
+                .ok_or(String::from("The log engine requires a data directory"))?;
+            Box::new(
+                LogEngine::open(data_directory, settings.snapshot_interval)
+                    .map_err(|err| format!("Unable to open log engine. Error: {}", err))?,
+            )
+        }
+        EngineKind::Lru => {
+            let capacity = settings
+                .capacity
+                .ok_or(String::from("The lru engine requires a capacity"))?;
+            Box::new(LruEngine::new(capacity))
+        }
+    };
+
+    println!("Using {} storage engine", engine.name());
+    Ok(engine)
+}
@@ -0,0 +1,475 @@
+use rocket::serde::json;
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+
+use super::{entry_size, Change, Entry, StorageEngine};
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const LOG_FILE: &str = "wal.log";
+const DEFAULT_SNAPSHOT_INTERVAL: usize = 1000; // Number of logged writes between each snapshot
+
+// A single entry in the write-ahead log, one JSON object per line
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+enum LogRecord {
+    Put { key: String, entry: Entry },
+    Delete { key: String },
+}
+
+struct WriteAheadLog {
+    directory: PathBuf,
+    file: File,
+    length: u64,   // Length of the complete records in the file
+    damaged: bool, // Part of a record could not be removed again, so nothing more may be appended
+    writes_since_snapshot: usize,
+    snapshot_interval: usize,
+}
+
+// HashMap kept durable by a write-ahead log and periodic snapshots in a data directory
+pub struct LogEngine {
+    storage: RwLock<HashMap<String, Entry>>,
+    log: Mutex<WriteAheadLog>,
+    bytes: AtomicUsize, // Only changed under the write lock of storage
+}
+
+impl LogEngine {
+    pub fn open(data_directory: PathBuf, snapshot_interval: Option<usize>) -> io::Result<Self> {
+        fs::create_dir_all(&data_directory)?;
+
+        // Rebuild the state from the last snapshot, then replay every write logged after it
+        let mut map = read_snapshot(&data_directory)?;
+        let (replayed, replayed_length) = replay_log(&data_directory, &mut map)?;
+
+        println!(
+            "Initialized persistent storage in {:?}, recovered {} keys ({} from log)",
+            data_directory,
+            map.len(),
+            replayed
+        );
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(data_directory.join(LOG_FILE))?;
+
+        // Cut off a torn record, so new records start on a line of their own
+        if file.metadata()?.len() > replayed_length {
+            file.set_len(replayed_length)?;
+        }
+
+        let bytes = map.iter().map(|(key, entry)| entry_size(key, entry)).sum();
+        Ok(LogEngine {
+            storage: RwLock::new(map),
+            log: Mutex::new(WriteAheadLog {
+                directory: data_directory,
+                file,
+                length: replayed_length,
+                damaged: false,
+                writes_since_snapshot: replayed,
+                snapshot_interval: snapshot_interval.unwrap_or(DEFAULT_SNAPSHOT_INTERVAL),
+            }),
+            bytes: AtomicUsize::new(bytes),
+        })
+    }
+
+    fn write(&self, record: LogRecord) -> io::Result<Option<Entry>> {
+        let mut storage = self.storage.write().expect("RWLock poisoned");
+        let mut log = self.log.lock().expect("Mutex poisoned");
+        self.apply(&mut storage, &mut log, record)
+    }
+
+    // The record must be in the log before the write becomes visible. Called with both locks held.
+    fn apply(
+        &self,
+        storage: &mut HashMap<String, Entry>,
+        log: &mut WriteAheadLog,
+        record: LogRecord,
+    ) -> io::Result<Option<Entry>> {
+        log.append(&record)?;
+        let (key, previous) = match record {
+            LogRecord::Put { key, entry } => {
+                self.bytes
+                    .fetch_add(entry_size(&key, &entry), Ordering::Relaxed);
+                let previous = storage.insert(key.clone(), entry);
+                (key, previous)
+            }
+            LogRecord::Delete { key } => {
+                let previous = storage.remove(&key);
+                (key, previous)
+            }
+        };
+        if let Some(previous) = previous.as_ref() {
+            self.bytes
+                .fetch_sub(entry_size(&key, previous), Ordering::Relaxed);
+        }
+
+        // The write is in the log already, a failed snapshot is tried again on the next write
+        if log.writes_since_snapshot >= log.snapshot_interval {
+            if let Err(err) = log.snapshot(storage) {
+                println!("Could not write snapshot. Error: {}", err);
+            }
+        }
+        Ok(previous)
+    }
+}
+
+impl StorageEngine for LogEngine {
+    fn get(&self, key: &str) -> Option<Entry> {
+        let storage = self.storage.read().expect("RWLock poisoned");
+        storage.get(key).cloned()
+    }
+
+    fn delete(&self, key: &str) -> io::Result<Option<Entry>> {
+        // Nothing to log if the key is not stored
+        if self.get(key).is_none() {
+            return Ok(None);
+        }
+
+        self.write(LogRecord::Delete {
+            key: key.to_string(),
+        })
+    }
+
+    fn update(
+        &self,
+        key: &str,
+        change: &mut dyn FnMut(Option<&Entry>) -> Change,
+    ) -> io::Result<bool> {
+        let mut storage = self.storage.write().expect("RWLock poisoned");
+        let mut log = self.log.lock().expect("Mutex poisoned");
+
+        let record = match change(storage.get(key)) {
+            Change::Keep => return Ok(false),
+            Change::Put(entry) => LogRecord::Put {
+                key: key.to_string(),
+                entry,
+            },
+            Change::Remove => LogRecord::Delete {
+                key: key.to_string(),
+            },
+        };
+        self.apply(&mut storage, &mut log, record)?;
+        Ok(true)
+    }
+
+    fn scan(&self) -> Vec<(String, Entry)> {
+        let storage = self.storage.read().expect("RWLock poisoned");
+        storage
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.storage.read().expect("RWLock poisoned").len()
+    }
+
+    fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn name(&self) -> &'static str {
+        "log"
+    }
+}
+
+impl WriteAheadLog {
+    fn append(&mut self, record: &LogRecord) -> io::Result<()> {
+        if self.damaged {
+            return Err(io::Error::other(
+                "Write-ahead log holds a partial record, refusing further writes",
+            ));
+        }
+
+        let mut line = json::to_string(record).map_err(io::Error::other)?;
+        line.push('\n');
+
+        // Once written the record is with the OS, so it survives the process dying. A write that fails
+        // halfway, such as on a full disk, is cut off again, or the next record would be joined onto it.
+        if let Err(err) = self.file.write_all(line.as_bytes()) {
+            if let Err(truncate_err) = self.file.set_len(self.length) {
+                println!(
+                    "Could not remove partial record from write-ahead log. Error: {}",
+                    truncate_err
+                );
+                self.damaged = true;
+            }
+            return Err(err);
+        }
+        self.length += line.len() as u64;
+        self.writes_since_snapshot += 1;
+        Ok(())
+    }
+
+    fn snapshot(&mut self, storage: &HashMap<String, Entry>) -> io::Result<()> {
+        let snapshot_path = self.directory.join(SNAPSHOT_FILE);
+        let temporary_path = self.directory.join(format!("{}.tmp", SNAPSHOT_FILE));
+
+        // Write to a temporary file first so a crash mid-snapshot leaves the old snapshot intact
+        let mut temporary = File::create(&temporary_path)?;
+        temporary.write_all(
+            json::to_string(storage)
+                .map_err(io::Error::other)?
+                .as_bytes(),
+        )?;
+        temporary.sync_all()?;
+        fs::rename(&temporary_path, &snapshot_path)?;
+
+        // Everything in the log is now covered by the snapshot
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.length = 0;
+        self.writes_since_snapshot = 0;
+        Ok(())
+    }
+}
+
+fn read_snapshot(directory: &Path) -> io::Result<HashMap<String, Entry>> {
+    let contents = match fs::read_to_string(directory.join(SNAPSHOT_FILE)) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err),
+    };
+
+    json::from_str(&contents).map_err(io::Error::other)
+}
+
+// Returns the number of records replayed, and the length of the log up to the end of the last of them.
+// Only the last record may be incomplete, a damaged record before it is an error.
+fn replay_log(directory: &Path, map: &mut HashMap<String, Entry>) -> io::Result<(usize, u64)> {
+    let file = match File::open(directory.join(LOG_FILE)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+        Err(err) => return Err(err),
+    };
+
+    let mut replayed = 0;
+    let mut replayed_length = 0;
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let length = reader.read_line(&mut line)?;
+        if length == 0 {
+            break;
+        }
+
+        // A record torn while the process died lacks its newline, and is always the last one
+        let record = match line.strip_suffix('\n').map(json::from_str::<LogRecord>) {
+            Some(Ok(record)) => record,
+            Some(Err(err)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Damaged record after {} bytes of write-ahead log. Error: {}",
+                        replayed_length, err
+                    ),
+                ))
+            }
+            None => {
+                println!("Ignoring incomplete record at end of write-ahead log.");
+                break;
+            }
+        };
+
+        match record {
+            LogRecord::Put { key, entry } => map.insert(key, entry),
+            LogRecord::Delete { key } => map.remove(&key),
+        };
+        replayed += 1;
+        replayed_length += length as u64;
+    }
+
+    Ok((replayed, replayed_length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    // Fresh data directory per test, removed again when the test is done
+    struct TestDirectory(PathBuf);
+
+    impl TestDirectory {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("log-engine-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            TestDirectory(path)
+        }
+    }
+
+    impl Drop for TestDirectory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn entry(value: &str, version: u64) -> Entry {
+        Entry {
+            value: String::from(value),
+            version,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn writes_survive_reopening() {
+        let directory = TestDirectory::new("reopen");
+        {
+            let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+            assert!(engine.put_if_newer("a", entry("1", 1)).unwrap());
+            assert!(engine.put_if_newer("b", entry("2", 1)).unwrap());
+            assert!(engine.put_if_newer("a", entry("3", 2)).unwrap());
+            assert_eq!(engine.delete("b").unwrap(), Some(entry("2", 1)));
+            assert_eq!(engine.delete("missing").unwrap(), None);
+        }
+
+        let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+        assert_eq!(engine.get("a"), Some(entry("3", 2)));
+        assert_eq!(engine.get("b"), None);
+        assert_eq!(engine.len(), 1);
+        assert_eq!(engine.bytes(), 2);
+    }
+
+    #[test]
+    fn torn_last_record_is_ignored() {
+        let directory = TestDirectory::new("torn");
+        {
+            let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+            assert!(engine.put_if_newer("a", entry("1", 1)).unwrap());
+            assert!(engine.put_if_newer("b", entry("2", 1)).unwrap());
+        }
+
+        // The process died halfway through appending a record
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(directory.0.join(LOG_FILE))
+            .unwrap();
+        log.write_all(br#"{"Put":{"key":"c","entry":{"val"#)
+            .unwrap();
+        drop(log);
+
+        let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+        assert_eq!(engine.get("a"), Some(entry("1", 1)));
+        assert_eq!(engine.get("b"), Some(entry("2", 1)));
+        assert_eq!(engine.get("c"), None);
+        assert_eq!(engine.len(), 2);
+
+        // Writes after the torn record are still replayed on the next start
+        assert!(engine.put_if_newer("d", entry("4", 1)).unwrap());
+        drop(engine);
+        let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+        assert_eq!(engine.get("d"), Some(entry("4", 1)));
+    }
+
+    #[test]
+    fn versions_are_decided_by_the_engine() {
+        let directory = TestDirectory::new("versions");
+        let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+
+        assert_eq!(
+            engine.put_next_version("a", String::from("1")).unwrap(),
+            entry("1", 1)
+        );
+        assert_eq!(
+            engine.put_next_version("a", String::from("2")).unwrap(),
+            entry("2", 2)
+        );
+
+        // Older versions are refused, equal versions are settled by the value
+        assert!(!engine.put_if_newer("a", entry("9", 1)).unwrap());
+        assert!(!engine.put_if_newer("a", entry("1", 2)).unwrap());
+        assert!(!engine.put_if_newer("a", entry("2", 2)).unwrap());
+        assert!(engine.put_if_newer("a", entry("3", 2)).unwrap());
+        assert!(engine.put_if_newer("b", entry("4", 7)).unwrap());
+        drop(engine);
+
+        let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+        assert_eq!(engine.get("a"), Some(entry("3", 2)));
+        assert_eq!(engine.get("b"), Some(entry("4", 7)));
+    }
+
+    #[test]
+    fn deletes_leave_tombstones_until_purged() {
+        let directory = TestDirectory::new("tombstones");
+        let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+        assert!(engine.put_if_newer("a", entry("1", 3)).unwrap());
+
+        assert_eq!(
+            engine.delete_next_version("a").unwrap(),
+            Some(entry("1", 3))
+        );
+        assert_eq!(engine.delete_next_version("a").unwrap(), None);
+        assert_eq!(engine.delete_next_version("missing").unwrap(), None);
+
+        // An older copy from a replica does not bring the key back
+        let tombstone = engine.get("a").unwrap();
+        assert!(tombstone.is_deleted());
+        assert_eq!(tombstone.version, 4);
+        assert!(!engine.put_if_newer("a", entry("1", 3)).unwrap());
+        drop(engine);
+
+        let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+        assert_eq!(engine.get("a"), Some(tombstone.clone()));
+        assert_eq!(
+            engine
+                .purge_tombstones(tombstone.deleted_at.unwrap())
+                .unwrap(),
+            0
+        );
+        assert_eq!(engine.purge_tombstones(u64::MAX).unwrap(), 1);
+        assert_eq!(engine.get("a"), None);
+        assert_eq!(engine.len(), 0);
+    }
+
+    #[test]
+    fn damaged_record_before_the_end_is_an_error() {
+        let directory = TestDirectory::new("damaged");
+        {
+            let engine = LogEngine::open(directory.0.clone(), None).unwrap();
+            assert!(engine.put_if_newer("a", entry("1", 1)).unwrap());
+        }
+
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(directory.0.join(LOG_FILE))
+            .unwrap();
+        log.write_all(b"{\"Put\":{\"key\":\"b\"\n").unwrap();
+        log.write_all(b"{\"Delete\":{\"key\":\"a\"}}\n").unwrap();
+        drop(log);
+
+        // Acknowledged writes follow the damaged record, so none of the log may be cut off
+        let length = fs::metadata(directory.0.join(LOG_FILE)).unwrap().len();
+        assert!(LogEngine::open(directory.0.clone(), None).is_err());
+        assert_eq!(
+            fs::metadata(directory.0.join(LOG_FILE)).unwrap().len(),
+            length
+        );
+    }
+
+    #[test]
+    fn log_is_replayed_on_top_of_snapshot() {
+        let directory = TestDirectory::new("snapshot");
+        {
+            let engine = LogEngine::open(directory.0.clone(), Some(2)).unwrap();
+            assert!(engine.put_if_newer("a", entry("1", 1)).unwrap());
+            assert!(engine.put_if_newer("b", entry("2", 1)).unwrap()); // Second write takes a snapshot and empties the log
+            assert!(engine.put_if_newer("a", entry("3", 2)).unwrap());
+            engine.delete("b").unwrap();
+            assert!(engine.put_if_newer("c", entry("5", 1)).unwrap());
+        }
+        assert!(directory.0.join(SNAPSHOT_FILE).exists());
+
+        let engine = LogEngine::open(directory.0.clone(), Some(2)).unwrap();
+        assert_eq!(engine.get("a"), Some(entry("3", 2)));
+        assert_eq!(engine.get("b"), None);
+        assert_eq!(engine.get("c"), Some(entry("5", 1)));
+        assert_eq!(engine.len(), 2);
+    }
+}
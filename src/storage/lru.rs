@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::Mutex;
+
+use super::{entry_size, Change, Entry, StorageEngine};
+
+struct LruState {
+    entries: HashMap<String, (Entry, u64)>, // key -> (entry, last access tick)
+    recency: BTreeMap<u64, String>,         // last access tick -> key, oldest first
+    tick: u64,
+    bytes: usize, // Size of the keys and values in entries
+}
+
+// Memory-bounded engine holding at most `capacity` keys, evicting the least recently used key when full
+pub struct LruEngine {
+    capacity: usize,
+    state: Mutex<LruState>,
+}
+
+impl LruEngine {
+    pub fn new(capacity: usize) -> Self {
+        if capacity == 0 {
+            panic!("LRU capacity must be at least one key.");
+        }
+
+        LruEngine {
+            capacity,
+            state: Mutex::new(LruState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                bytes: 0,
+            }),
+        }
+    }
+}
+
+impl LruState {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some((_value, last_access)) = self.entries.get_mut(key) {
+            self.recency.remove(last_access);
+            *last_access = tick;
+            self.recency.insert(tick, key.to_string());
+        }
+    }
+
+    // Replaces or adds the entry, evicting the least recently used key if that would go over capacity
+    fn insert(&mut self, key: &str, entry: Entry, capacity: usize) {
+        let size = entry_size(key, &entry);
+        if let Some((old_entry, _tick)) = self.entries.get_mut(key) {
+            let old_size = entry_size(key, old_entry);
+            *old_entry = entry;
+            self.bytes = self.bytes + size - old_size;
+            self.touch(key);
+            return;
+        }
+
+        if self.entries.len() >= capacity {
+            if let Some((_tick, evicted)) = self.recency.pop_first() {
+                if let Some((evicted_entry, _tick)) = self.entries.remove(&evicted) {
+                    self.bytes -= entry_size(&evicted, &evicted_entry);
+                }
+                println!("Evicted key {} from LRU storage", evicted);
+            }
+        }
+
+        self.bytes += size;
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.insert(key.to_string(), (entry, tick));
+        self.recency.insert(tick, key.to_string());
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let (entry, tick) = self.entries.remove(key)?;
+        self.recency.remove(&tick);
+        self.bytes -= entry_size(key, &entry);
+        Some(entry)
+    }
+}
+
+impl StorageEngine for LruEngine {
+    fn get(&self, key: &str) -> Option<Entry> {
+        // Reads update recency, so even lookups need exclusive access
+        let mut state = self.state.lock().expect("Mutex poisoned");
+        state.touch(key);
+        state.entries.get(key).map(|(entry, _tick)| entry.clone())
+    }
+
+    fn delete(&self, key: &str) -> io::Result<Option<Entry>> {
+        let mut state = self.state.lock().expect("Mutex poisoned");
+        Ok(state.remove(key))
+    }
+
+    fn update(
+        &self,
+        key: &str,
+        change: &mut dyn FnMut(Option<&Entry>) -> Change,
+    ) -> io::Result<bool> {
+        let mut state = self.state.lock().expect("Mutex poisoned");
+        match change(state.entries.get(key).map(|(entry, _tick)| entry)) {
+            Change::Keep => return Ok(false),
+            Change::Put(entry) => state.insert(key, entry, self.capacity),
+            Change::Remove => {
+                state.remove(key);
+            }
+        }
+        Ok(true)
+    }
+
+    fn scan(&self) -> Vec<(String, Entry)> {
+        let state = self.state.lock().expect("Mutex poisoned");
+        state
+            .entries
+            .iter()
+            .map(|(key, (entry, _tick))| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().expect("Mutex poisoned").entries.len()
+    }
+
+    fn bytes(&self) -> usize {
+        self.state.lock().expect("Mutex poisoned").bytes
+    }
+
+    fn name(&self) -> &'static str {
+        "lru"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(value: &str, version: u64) -> Entry {
+        Entry {
+            value: String::from(value),
+            version,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn least_recently_used_key_is_evicted() {
+        let engine = LruEngine::new(2);
+        assert!(engine.put_if_newer("a", entry("1", 1)).unwrap());
+        assert!(engine.put_if_newer("b", entry("2", 1)).unwrap());
+        assert!(engine.put_if_newer("c", entry("3", 1)).unwrap());
+
+        assert_eq!(engine.len(), 2);
+        assert_eq!(engine.get("a"), None);
+        assert_eq!(engine.get("b"), Some(entry("2", 1)));
+        assert_eq!(engine.get("c"), Some(entry("3", 1)));
+    }
+
+    #[test]
+    fn reads_refresh_recency() {
+        let engine = LruEngine::new(2);
+        assert!(engine.put_if_newer("a", entry("1", 1)).unwrap());
+        assert!(engine.put_if_newer("b", entry("2", 1)).unwrap());
+
+        // Reading a makes b the least recently used key
+        assert_eq!(engine.get("a"), Some(entry("1", 1)));
+        assert!(engine.put_if_newer("c", entry("3", 1)).unwrap());
+
+        assert_eq!(engine.get("b"), None);
+        assert_eq!(engine.get("a"), Some(entry("1", 1)));
+        assert_eq!(engine.get("c"), Some(entry("3", 1)));
+    }
+
+    #[test]
+    fn bytes_follow_evictions_and_overwrites() {
+        let engine = LruEngine::new(2);
+        assert!(engine.put_if_newer("a", entry("one", 1)).unwrap());
+        assert!(engine.put_if_newer("b", entry("two", 1)).unwrap());
+        assert_eq!(engine.bytes(), 8);
+
+        // Overwriting counts only the new value
+        assert!(engine.put_if_newer("a", entry("longer", 2)).unwrap());
+        assert_eq!(engine.bytes(), 11);
+
+        // Evicting b removes its key and value
+        assert!(engine.put_if_newer("c", entry("x", 1)).unwrap());
+        assert_eq!(engine.get("b"), None);
+        assert_eq!(engine.bytes(), 9);
+
+        assert_eq!(engine.delete("a").unwrap(), Some(entry("longer", 2)));
+        assert_eq!(engine.bytes(), 2);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+use super::{entry_size, Change, Entry, StorageEngine};
+
+// Plain in-memory HashMap, everything is lost when the process exits
+pub struct MemoryEngine {
+    storage: RwLock<HashMap<String, Entry>>,
+    bytes: AtomicUsize, // Only changed under the write lock of storage
+}
+
+impl MemoryEngine {
+    pub fn new() -> Self {
+        MemoryEngine {
+            storage: RwLock::new(HashMap::new()),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemoryEngine {
+    fn insert(&self, storage: &mut HashMap<String, Entry>, key: &str, entry: Entry) {
+        self.bytes
+            .fetch_add(entry_size(key, &entry), Ordering::Relaxed);
+        if let Some(previous) = storage.insert(key.to_string(), entry) {
+            self.bytes
+                .fetch_sub(entry_size(key, &previous), Ordering::Relaxed);
+        }
+    }
+
+    fn remove(&self, storage: &mut HashMap<String, Entry>, key: &str) -> Option<Entry> {
+        let removed = storage.remove(key);
+        if let Some(entry) = removed.as_ref() {
+            self.bytes
+                .fetch_sub(entry_size(key, entry), Ordering::Relaxed);
+        }
+        removed
+    }
+}
+
+impl StorageEngine for MemoryEngine {
+    fn get(&self, key: &str) -> Option<Entry> {
+        let storage = self.storage.read().expect("RWLock poisoned");
+        storage.get(key).cloned()
+    }
+
+    fn delete(&self, key: &str) -> io::Result<Option<Entry>> {
+        let mut storage = self.storage.write().expect("RWLock poisoned");
+        Ok(self.remove(&mut storage, key))
+    }
+
+    fn update(
+        &self,
+        key: &str,
+        change: &mut dyn FnMut(Option<&Entry>) -> Change,
+    ) -> io::Result<bool> {
+        let mut storage = self.storage.write().expect("RWLock poisoned");
+        match change(storage.get(key)) {
+            Change::Keep => return Ok(false),
+            Change::Put(entry) => self.insert(&mut storage, key, entry),
+            Change::Remove => {
+                self.remove(&mut storage, key);
+            }
+        }
+        Ok(true)
+    }
+
+    fn scan(&self) -> Vec<(String, Entry)> {
+        let storage = self.storage.read().expect("RWLock poisoned");
+        storage
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.storage.read().expect("RWLock poisoned").len()
+    }
+
+    fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+}
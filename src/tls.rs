@@ -0,0 +1,104 @@
+use rocket::config::{MutualTls, TlsConfig};
+use rocket::figment::Figment;
+use rocket::mtls::Certificate;
+use rocket::Request;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+static CONTEXT: OnceLock<TlsContext> = OnceLock::new();
+
+// Files of a node, all in PEM. The certificate is shown both to clients and, as client certificate,
+// to the nodes it sends requests to, so it must name the hostname other nodes reach it by.
+#[derive(Clone, Debug)]
+pub struct TlsSettings {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+    pub ca: PathBuf, // Only client certificates signed by this CA are accepted
+}
+
+// The settings along with what the client needs from them, read once so a missing or broken file
+// stops the node at startup instead of failing every request
+pub struct TlsContext {
+    pub settings: TlsSettings,
+    ca_certificate: reqwest::Certificate,
+    identity: reqwest::Identity,
+}
+
+pub fn load(settings: TlsSettings) -> Result<TlsContext, String> {
+    let read = |path: &PathBuf| {
+        fs::read(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))
+    };
+
+    let ca_certificate = reqwest::Certificate::from_pem(&read(&settings.ca)?)
+        .map_err(|err| format!("Invalid CA certificate: {}", err))?;
+
+    // The client wants the certificate and its key in one PEM buffer
+    let mut identity_pem = read(&settings.certificate)?;
+    identity_pem.push(b'\n');
+    identity_pem.extend(read(&settings.key)?);
+    let identity = reqwest::Identity::from_pem(&identity_pem)
+        .map_err(|err| format!("Invalid certificate or key: {}", err))?;
+
+    return Ok(TlsContext {
+        settings,
+        ca_certificate,
+        identity,
+    });
+}
+
+// Set once at startup, before the first request is sent or any node is launched
+pub fn set_context(context: TlsContext) -> Result<(), String> {
+    CONTEXT
+        .set(context)
+        .map_err(|_context| String::from("TLS has already been set up"))
+}
+
+pub fn context() -> Option<&'static TlsContext> {
+    return CONTEXT.get();
+}
+
+pub fn scheme() -> &'static str {
+    match context() {
+        Some(_context) => "https",
+        None => "http",
+    }
+}
+
+// Trusts only the CA of the network, and authenticates with the certificate of the node
+pub fn configure_client(builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+    match context() {
+        None => return builder,
+        Some(context) => {
+            return builder
+                .use_rustls_tls()
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(context.ca_certificate.clone())
+                .identity(context.identity.clone())
+                .https_only(true)
+        }
+    }
+}
+
+// Serves HTTPS. Clients may connect without a certificate, but one that is shown has to be signed by
+// the CA, or the connection is turned away.
+pub fn configure_rocket(figment: Figment) -> Figment {
+    match context() {
+        None => return figment,
+        Some(context) => {
+            let tls_config =
+                TlsConfig::from_paths(&context.settings.certificate, &context.settings.key)
+                    .with_mutual(MutualTls::from_path(&context.settings.ca).mandatory(false));
+            return figment.merge(("tls", tls_config));
+        }
+    }
+}
+
+// Whether the request came over a connection with a certificate signed by the CA, as other nodes and
+// admins use. Always true without TLS.
+pub async fn has_client_certificate(request: &Request<'_>) -> bool {
+    if context().is_none() {
+        return true;
+    }
+    return request.guard::<Certificate<'_>>().await.is_success();
+}
@@ -0,0 +1,75 @@
+use rocket::tokio;
+
+use crate::{http_connect, membership, JoinRequest, Node, SuppliedNode};
+
+// Every virtual node of a process is a full node on its own port, with its own position, range,
+// successor and keys. The first one is the node the process is addressed by, and passes ring
+// membership and crash simulation on to the others.
+
+// Whether both nodes are hosted by the same process, which takes all of its virtual nodes down at once
+pub fn is_same_process(a: &Node, b: &Node) -> bool {
+    a.hostname == b.hostname && a.process == b.process
+}
+
+// Ports of the other virtual nodes hosted by the process listening on `port`
+pub fn siblings(hostname: &str, port: u16, count: u16) -> Vec<SuppliedNode> {
+    (1..count)
+        .map(|index| SuppliedNode {
+            hostname: String::from(hostname),
+            port: port
+                .checked_add(index)
+                .expect("Not enough ports above A1_PORT for all virtual nodes."),
+        })
+        .collect()
+}
+
+// Joins the other virtual nodes to the ring as described by the join request, one after another.
+// Runs in the background, as each join calls back into the node that started it.
+pub fn join_siblings(siblings: &[SuppliedNode], join_request: JoinRequest) {
+    let body =
+        rocket::serde::json::to_string(&join_request).expect("Unable to serialize join request.");
+    send_to_siblings(
+        siblings,
+        http_connect::WriteOperations::Put,
+        "network/join",
+        body,
+    );
+}
+
+// Sends the same request to every other virtual node, in the background
+pub fn send_to_siblings(
+    siblings: &[SuppliedNode],
+    operation: http_connect::WriteOperations,
+    path: &'static str,
+    body: String,
+) {
+    if siblings.is_empty() {
+        return;
+    }
+
+    let siblings = siblings.to_vec();
+    let sender = membership::current_node();
+    let send = async move {
+        for sibling in siblings {
+            match http_connect::write_body_to_node(
+                operation,
+                &sibling.hostname,
+                sibling.port,
+                path,
+                "application/json",
+                body.clone(),
+            )
+            .await
+            {
+                Ok(_response) => println!("Virtual node {} did {}", sibling.port, path),
+                Err(_err) => println!("Virtual node {} failed {}", sibling.port, path),
+            }
+        }
+    };
+
+    // Sent on behalf of the node that passes the request on, like any request it sends itself
+    match sender {
+        Some(node_config) => tokio::spawn(membership::as_node(node_config, send)),
+        None => tokio::spawn(send),
+    };
+}